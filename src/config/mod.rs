@@ -5,7 +5,7 @@ use log::debug;
 use serde::Deserialize;
 mod profile;
 
-pub use profile::{Profile, CmdSchedule};
+pub use profile::{Profile, CmdSchedule, TrackMode};
 
 /// Main config for project. It is loaded from TOML or YAML in that order
 #[derive(Debug, Deserialize)]
@@ -14,7 +14,11 @@ pub struct Config {
 }
 
 fn parse_config(content: &str) -> anyhow::Result<Config> {
-    Ok(toml::from_str(content)?)
+    let config: Config = toml::from_str(content)?;
+    for profile in &config.profiles {
+        profile.validate().map_err(anyhow::Error::msg)?;
+    }
+    Ok(config)
 }
 
 pub fn read_config(p: Option<PathBuf>) -> anyhow::Result<Config> {
@@ -77,4 +81,383 @@ mod tests {
         assert_eq!(c.profiles[0].commands.len(), 2, "non matching number of commands on profile1");
         Ok(())
     }
+
+    #[test]
+    fn config_exclude() -> anyhow::Result<()> {
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { cmdline = "ollama runner" }
+            exclude = [{ name = "ollama-helper" }, { cmdline = "^sh -c", regex = true }]
+            commands = []
+
+            [[profiles]]
+            matching = { cmdline = "ollama runner" }
+            exclude_self = false
+            commands = []
+        "###};
+
+        let c = parse_config(config)?;
+        assert_eq!(c.profiles[0].exclude.len(), 2);
+        assert!(c.profiles[0].exclude_self, "self exclusion should be enabled by default");
+        assert!(c.profiles[1].exclude.is_empty());
+        assert!(!c.profiles[1].exclude_self);
+        assert!(!c.profiles[0].include_descendants);
+        Ok(())
+    }
+
+    #[test]
+    fn config_include_descendants() -> anyhow::Result<()> {
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "make" }
+            include_descendants = true
+            commands = []
+        "###};
+
+        let c = parse_config(config)?;
+        assert!(c.profiles[0].include_descendants);
+        Ok(())
+    }
+
+    #[test]
+    fn config_track_mode() -> anyhow::Result<()> {
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "ffmpeg" }
+            track = "per_pid"
+            commands = []
+
+            [[profiles]]
+            matching = { name = "ffmpeg" }
+            commands = []
+        "###};
+
+        let c = parse_config(config)?;
+        assert_eq!(c.profiles[0].track, TrackMode::PerPid);
+        assert_eq!(c.profiles[1].track, TrackMode::Group);
+        assert!(parse_config(&config.replace("per_pid", "per_user")).is_err());
+
+        // conditions on the whole group never fire for a single instance
+        let group_only = config.replacen("commands = []", indoc! {r#"
+            [[profiles.commands]]
+            condition = { not_seen = "10s" }
+            exec = ["echo", "down"]
+        "#}, 1);
+        let err = parse_config(&group_only).unwrap_err().to_string();
+        assert!(err.contains("`not_seen` conditions never fire"), "{err}");
+        assert!(parse_config(&group_only.replace(r#"not_seen = "10s""#, "count_below = 1")).is_err());
+        assert!(parse_config(&group_only.replace("per_pid", "group")).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn config_count_conditions() -> anyhow::Result<()> {
+        use crate::process::ProcCondition;
+        use std::time::Duration;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "worker" }
+
+            [[profiles.commands]]
+            condition = { count_above = 8, for = "30s" }
+            exec = ["echo", "too many workers"]
+
+            [[profiles.commands]]
+            condition = { count_below = 2 }
+            exec = ["echo", "not enough workers"]
+        "###};
+
+        let c = parse_config(config)?;
+        let commands = &c.profiles[0].commands;
+        assert!(matches!(commands[0].condition,
+            ProcCondition::CountAbove { count: 8, span } if span == Duration::from_secs(30)));
+        assert!(matches!(commands[1].condition,
+            ProcCondition::CountBelow { count: 2, span: Duration::ZERO }));
+        Ok(())
+    }
+
+    #[test]
+    fn config_invalid_conditions() {
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "worker" }
+
+            [[profiles.commands]]
+            condition = { seen = "5s", for = "10s" }
+            exec = ["echo", "seen"]
+        "###};
+        let err = parse_config(config).unwrap_err().to_string();
+        assert!(err.contains("unknown key `for`"), "{err}");
+
+        let err = parse_config(&config.replace("seen", "sen")).unwrap_err().to_string();
+        assert!(err.contains("invalid condition"), "{err}");
+
+        let invalid = config.replace(r#"seen = "5s", for = "10s""#, r#"flapping = { restarts = 3, within = "1m", for = "1m" }"#);
+        assert!(parse_config(&invalid).is_err());
+    }
+
+    #[test]
+    fn config_cpu_conditions() -> anyhow::Result<()> {
+        use crate::process::{Aggregate, ProcCondition};
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "ffmpeg" }
+
+            [[profiles.commands]]
+            condition = { cpu_above = 100, for = "10m", aggregate = "max" }
+            exec = ["echo", "busy"]
+
+            [[profiles.commands]]
+            condition = { cpu_below = 0.5 }
+            exec = ["echo", "idle"]
+        "###};
+
+        let c = parse_config(config)?;
+        let commands = &c.profiles[0].commands;
+        assert!(matches!(commands[0].condition,
+            ProcCondition::CpuAbove { percent, aggregate: Aggregate::Max, .. } if percent == 100.0));
+        assert!(matches!(commands[1].condition,
+            ProcCondition::CpuBelow { aggregate: Aggregate::Sum, .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn config_memory_conditions() -> anyhow::Result<()> {
+        use crate::process::{Aggregate, MemoryKind, ProcCondition};
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "electron" }
+
+            [[profiles.commands]]
+            condition = { memory_above = "2GiB", for = "5m" }
+            exec = ["echo", "leaking"]
+
+            [[profiles.commands]]
+            condition = { memory_above = "50%", kind = "virtual", aggregate = "max" }
+            exec = ["echo", "leaking"]
+        "###};
+
+        let c = parse_config(config)?;
+        let commands = &c.profiles[0].commands;
+        assert!(matches!(commands[0].condition,
+            ProcCondition::MemoryAbove { bytes: 0x8000_0000, kind: MemoryKind::Rss, aggregate: Aggregate::Sum, .. }));
+        assert!(matches!(commands[1].condition,
+            ProcCondition::MemoryAbove { kind: MemoryKind::Virtual, aggregate: Aggregate::Max, .. }));
+
+        let invalid = config.replace("2GiB", "2 parsecs");
+        assert!(parse_config(&invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_io_conditions() -> anyhow::Result<()> {
+        use crate::process::ProcCondition;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "restic" }
+
+            [[profiles.commands]]
+            condition = { io_read_above = "50MiB/s", for = "1m" }
+            exec = ["echo", "reading"]
+
+            [[profiles.commands]]
+            condition = { io_write_above = 1048576, aggregate = "max" }
+            exec = ["echo", "writing"]
+        "###};
+
+        let c = parse_config(config)?;
+        let commands = &c.profiles[0].commands;
+        assert!(matches!(commands[0].condition, ProcCondition::IoReadAbove { rate: 0x320_0000, .. }));
+        assert!(matches!(commands[1].condition, ProcCondition::IoWriteAbove { rate: 0x10_0000, .. }));
+
+        assert!(parse_config(&config.replace("50MiB/s", "10%")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_cpu_time_and_age_conditions() -> anyhow::Result<()> {
+        use crate::process::{Aggregate, ProcCondition};
+        use std::time::Duration;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "ssh-agent" }
+
+            [[profiles.commands]]
+            condition = { age_above = "1d" }
+            exec = ["pkill", "ssh-agent"]
+
+            [[profiles.commands]]
+            condition = { cpu_time_above = "2h", aggregate = "max" }
+            exec = ["echo", "busy agent"]
+        "###};
+
+        let c = parse_config(config)?;
+        let commands = &c.profiles[0].commands;
+        assert!(matches!(commands[0].condition,
+            ProcCondition::AgeAbove(age) if age == Duration::from_secs(86400)));
+        assert!(matches!(commands[1].condition,
+            ProcCondition::CpuTimeAbove { time, aggregate: Aggregate::Max } if time == Duration::from_secs(7200)));
+        Ok(())
+    }
+
+    #[test]
+    fn config_edge_conditions() -> anyhow::Result<()> {
+        use crate::process::ProcCondition;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "rsync" }
+
+            [[profiles.commands]]
+            condition = "started"
+            exec = ["logger", "rsync started"]
+
+            [[profiles.commands]]
+            condition = "exited"
+            exec = ["notify-send", "rsync finished"]
+        "###};
+
+        let c = parse_config(config)?;
+        let commands = &c.profiles[0].commands;
+        assert!(matches!(commands[0].condition, ProcCondition::Started));
+        assert!(matches!(commands[1].condition, ProcCondition::Exited));
+        assert!(parse_config(&config.replace("\"exited\"", "\"crashed\"")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_flapping_condition() -> anyhow::Result<()> {
+        use crate::process::ProcCondition;
+        use std::time::Duration;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "postgres" }
+
+            [[profiles.commands]]
+            condition = { flapping = { restarts = 5, within = "2m" } }
+            exec = ["notify-send", "postgres keeps crashing"]
+        "###};
+
+        let c = parse_config(config)?;
+        assert!(matches!(c.profiles[0].commands[0].condition,
+            ProcCondition::Flapping { restarts: 5, within } if within == Duration::from_secs(120)));
+        Ok(())
+    }
+
+    #[test]
+    fn config_status_condition() -> anyhow::Result<()> {
+        use crate::process::{ProcCondition, ProcStatus};
+        use std::time::Duration;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "mount.nfs" }
+            exclude_status = []
+
+            [[profiles.commands]]
+            condition = { status = { in = ["disk_sleep", "zombie"], for = "5m" } }
+            exec = ["notify-send", "nfs client stuck"]
+
+            [[profiles]]
+            matching = { name = "mount.nfs" }
+            commands = []
+        "###};
+
+        let c = parse_config(config)?;
+        assert!(c.profiles[0].exclude_status.is_empty());
+        assert_eq!(c.profiles[1].exclude_status, vec![ProcStatus::Stopped, ProcStatus::Dead, ProcStatus::Zombie]);
+        assert!(matches!(&c.profiles[0].commands[0].condition,
+            ProcCondition::Status { statuses, span }
+                if statuses == &[ProcStatus::DiskSleep, ProcStatus::Zombie] && *span == Duration::from_secs(300)));
+        assert!(parse_config(&config.replace("disk_sleep", "asleep")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_memory_growth_condition() -> anyhow::Result<()> {
+        use crate::process::{Aggregate, ProcCondition};
+        use std::time::Duration;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "leaky-daemon" }
+
+            [[profiles.commands]]
+            condition = { memory_growth = { above = "50MiB", per = "10m" } }
+            exec = ["systemctl", "--user", "restart", "leaky-daemon"]
+        "###};
+
+        let c = parse_config(config)?;
+        assert!(matches!(c.profiles[0].commands[0].condition,
+            ProcCondition::MemoryGrowth { bytes: 0x320_0000, per, aggregate: Aggregate::Sum } if per == Duration::from_secs(600)));
+        assert!(parse_config(&config.replace("50MiB", "fifty")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_fds_threads_children_conditions() -> anyhow::Result<()> {
+        use crate::process::{Aggregate, ProcCondition};
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { name = "java" }
+
+            [[profiles.commands]]
+            condition = { fds_above = 10000, for = "1m" }
+            exec = ["echo", "connection leak"]
+
+            [[profiles.commands]]
+            condition = { threads_above = 500, aggregate = "max" }
+            exec = ["echo", "thread explosion"]
+
+            [[profiles.commands]]
+            condition = { children_above = 20 }
+            exec = ["echo", "too many children"]
+        "###};
+
+        let c = parse_config(config)?;
+        let commands = &c.profiles[0].commands;
+        assert!(matches!(commands[0].condition, ProcCondition::FdsAbove { count: 10000, aggregate: Aggregate::Sum, .. }));
+        assert!(matches!(commands[1].condition, ProcCondition::ThreadsAbove { count: 500, aggregate: Aggregate::Max, .. }));
+        assert!(matches!(commands[2].condition, ProcCondition::ChildrenAbove { count: 20, .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn config_sockets() -> anyhow::Result<()> {
+        use crate::procfs::{Proto, SocketPort};
+        use crate::process::ProcCondition;
+
+        let config = indoc! {r###"
+            [[profiles]]
+            matching = { listening = { proto = "tcp", port = 5432 } }
+
+            [[profiles.commands]]
+            condition = { connected_to = { proto = "udp", port = 51820 }, for = "10s" }
+            exec = ["echo", "vpn is up"]
+        "###};
+
+        let c = parse_config(config)?;
+        assert_eq!(c.profiles[0].matching.to_string(), "listening[tcp:5432]");
+        assert!(matches!(c.profiles[0].commands[0].condition,
+            ProcCondition::ConnectedTo { port: SocketPort { proto: Proto::Udp, port: 51820 }, .. }));
+
+        // a typo in the protocol key does not fall back to tcp
+        assert!(parse_config(&config.replace(r#"proto = "tcp""#, r#"prot = "udp""#)).is_err());
+        assert!(parse_config(&config.replace(r#"proto = "udp""#, r#"prot = "udp""#)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_example() -> anyhow::Result<()> {
+        let c = parse_config(include_str!("../../config.example.toml"))?;
+        assert!(!c.profiles.is_empty());
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{matching::ProcessMatcher, process::ProcCondition};
+use crate::{matching::ProcessMatcher, process::{default_exclude_status, ProcCondition, ProcStatus}};
 
 use serde::Deserialize;
 use std::time::Duration;
@@ -10,6 +10,27 @@ pub struct Profile {
     /// pattern of process name to match against
     pub matching: ProcessMatcher,
 
+    /// processes matching any of these patterns are ignored
+    #[serde(default)]
+    pub exclude: Vec<ProcessMatcher>,
+
+    /// ignore pswatch itself and the commands it spawned, enabled by default
+    #[serde(default = "default_exclude_self")]
+    pub exclude_self: bool,
+
+    /// processes in any of these states are ignored, stopped, dead and zombie ones by default
+    #[serde(default = "default_exclude_status")]
+    pub exclude_status: Vec<ProcStatus>,
+
+    /// also track the descendants of the matched processes, conditions then apply to the
+    /// whole process trees
+    #[serde(default)]
+    pub include_descendants: bool,
+
+    /// whether the matched processes are tracked as a group or each PID on its own
+    #[serde(default)]
+    pub track: TrackMode,
+
     // pub pattern_in: PatternIn,
     /// List of commands to run when condition is met
     pub commands: Vec<CmdSchedule>,
@@ -22,11 +43,50 @@ pub struct Profile {
     pub keep_watch: bool,
 }
 
+impl Profile {
+    /// Checks the options that depend on each other
+    pub fn validate(&self) -> Result<(), String> {
+        if self.track == TrackMode::PerPid {
+            // an instance only exists once its process matched, it is never missing or counted
+            // with the other instances
+            let group_only = self.commands.iter().find_map(|cmd| match cmd.condition {
+                ProcCondition::NotSeen(_) => Some("not_seen"),
+                ProcCondition::CountAbove { .. } => Some("count_above"),
+                ProcCondition::CountBelow { .. } => Some("count_below"),
+                _ => None,
+            });
+            if let Some(condition) = group_only {
+                return Err(format!(
+                    "<{}>: `{}` conditions never fire with `track = \"per_pid\"`, track the processes as a group",
+                    self.matching, condition
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the matched processes of a profile are tracked
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackMode {
+    /// all matched processes share a single lifetime and command state
+    #[default]
+    Group,
+
+    /// every matched PID has its own lifetime and command state
+    PerPid,
+}
+
 /// default process watch interval
 fn default_watch_interval() -> Duration {
     Duration::from_secs(5)
 }
 
+fn default_exclude_self() -> bool {
+    true
+}
+
 /// CmdSchedule is the base configuration unit, it can be defined one or many times.
 /// It consists of a single condition coupled with one or more actions (exec commands for now)
 #[derive(Debug, Deserialize, Clone)]
@@ -1,5 +1,6 @@
 pub mod config;
 pub mod matching;
 pub mod process;
+pub mod procfs;
 pub mod sched;
 pub mod state;
@@ -1,16 +1,25 @@
-use std::{fmt::Display, os::unix::ffi::OsStrExt};
+use std::{collections::{BTreeMap, HashSet}, fmt::Display, fs, io, os::unix::ffi::OsStrExt, path::{Path, PathBuf}};
 
+use log::{debug, warn};
 use memchr::memmem;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 use serde::{de, Deserialize};
+use sysinfo::{Groups, Pid, ProcessRefreshKind, UpdateKind, Users};
+
+use crate::procfs::{self, SocketPort};
+
+/// Maximum length of a process name as stored by the kernel (`comm`), longer names are truncated
+pub const COMM_LEN: usize = 15;
 
 // TODO!:
 /// Match a process by a given `Criteria'
+/// `info` is the process table the process was taken from, it is used by criteria that
+/// need to look at other processes (parents, ancestors ...)
 pub trait MatchBy<Criteria>
 where
     Criteria: Display,
 {
-    fn match_by(&self, matcher: Criteria) -> bool;
+    fn match_by(&self, matcher: &Criteria, info: &sysinfo::System) -> bool;
 }
 
 //TODO: handle different type of patterns (String, Regex ...)
@@ -18,9 +27,19 @@ where
 
 /// A PatternMatcher for processes. Matches a running process given a generic pattern P
 trait MatchProcByPattern<P> {
-    fn matches_exe(&self, pattern: P) -> bool;
-    fn matches_cmdline(&self, pattern: P) -> bool;
-    fn matches_name(&self, pattern: P) -> bool;
+    fn matches_exe(&self, pattern: &P) -> bool;
+    fn matches_cmdline(&self, pattern: &P) -> bool;
+    fn matches_name(&self, pattern: &P) -> bool;
+    fn matches_cwd(&self, pattern: &P) -> bool;
+
+    /// matches the value of the `key` environment variable
+    fn matches_env(&self, key: &str, pattern: &P) -> bool;
+
+    /// matches a part of the process cgroup path, as selected by `part`
+    fn matches_cgroup(&self, part: CgroupPart, pattern: &P) -> bool;
+
+    /// matches the command line arguments selected by `position`, one by one
+    fn matches_arg(&self, position: ArgPosition, pattern: &P) -> bool;
 }
 
 // Raw structures for deseiralizing matchers
@@ -29,14 +48,203 @@ trait MatchProcByPattern<P> {
 enum PatternInRaw {
     ExePath(String),
     Cmdline(String),
-    Name(String)
+    Name(String),
+    Argv0(String),
+    Cwd(String),
+    Cgroup(String),
+    SystemdUnit(String),
+    SystemdSlice(String),
+}
+
+impl PatternInRaw {
+    /// Configuration key of the pattern
+    fn key(&self) -> &'static str {
+        match self {
+            PatternInRaw::ExePath(_) => "exe_path",
+            PatternInRaw::Cmdline(_) => "cmdline",
+            PatternInRaw::Name(_) => "name",
+            PatternInRaw::Argv0(_) => "argv0",
+            PatternInRaw::Cwd(_) => "cwd",
+            PatternInRaw::Cgroup(_) => "cgroup",
+            PatternInRaw::SystemdUnit(_) => "systemd_unit",
+            PatternInRaw::SystemdSlice(_) => "systemd_slice",
+        }
+    }
+
+    /// Whether the pattern is matched against a path, globs keep `*` within a path component
+    fn is_path(&self) -> bool {
+        matches!(self, PatternInRaw::ExePath(_) | PatternInRaw::Cwd(_) | PatternInRaw::Cgroup(_))
+    }
+}
+
+/// An environment variable is either matched by value or by presence: `true` when it must
+/// be set and `false` when it must not.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum EnvValueRaw {
+    Set(bool),
+    Value(String),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+enum OwnerRaw {
+    User(String),
+    Uid(u32),
+    Group(String),
+    Gid(u32),
+}
+
+impl OwnerRaw {
+    /// Configuration key of the owner
+    fn key(&self) -> &'static str {
+        match self {
+            OwnerRaw::User(_) => "user",
+            OwnerRaw::Uid(_) => "uid",
+            OwnerRaw::Group(_) => "group",
+            OwnerRaw::Gid(_) => "gid",
+        }
+    }
 }
 
+/// A matcher is either a single pattern or a combination of other matchers.
+/// Combinators are recursive and deserialized back through `ProcessMatcher`.
 #[derive(Deserialize, Clone, Debug)]
-struct ProcessMatcherRaw {
-    #[serde(flatten)]
-    pattern: PatternInRaw,
-    regex: Option<bool>
+#[serde(untagged)]
+enum ProcessMatcherRaw {
+    All {
+        all: Vec<ProcessMatcher>,
+    },
+    Any {
+        any: Vec<ProcessMatcher>,
+    },
+    Not {
+        not: Box<ProcessMatcher>,
+    },
+    Parent {
+        parent: Box<ProcessMatcher>,
+    },
+    Ancestor {
+        ancestor: Box<ProcessMatcher>,
+        depth: Option<usize>,
+    },
+    // must come before `Pattern` which would accept the optional `exe_path`
+    Pidfile {
+        pidfile: PathBuf,
+        exe_path: Option<String>,
+        #[serde(flatten)]
+        opts: PatternOpts,
+    },
+    Pattern {
+        #[serde(flatten)]
+        pattern: PatternInRaw,
+        #[serde(flatten)]
+        opts: PatternOpts,
+    },
+    Owner {
+        #[serde(flatten)]
+        owner: OwnerRaw,
+        #[serde(default)]
+        effective: bool,
+    },
+    CwdUnder {
+        cwd_under: PathBuf,
+    },
+    Env {
+        env: BTreeMap<String, EnvValueRaw>,
+        #[serde(flatten)]
+        opts: PatternOpts,
+    },
+    Args {
+        args: String,
+        index: Option<usize>,
+        #[serde(flatten)]
+        opts: PatternOpts,
+    },
+    Listening {
+        listening: SocketPort,
+    },
+}
+
+impl ProcessMatcherRaw {
+    /// Keys accepted by the variant. Untagged variants ignore the keys they do not know
+    /// and flattened fields cannot deny them, they are checked against this list instead.
+    fn keys(&self) -> Vec<&'static str> {
+        match self {
+            ProcessMatcherRaw::All { .. } => vec!["all"],
+            ProcessMatcherRaw::Any { .. } => vec!["any"],
+            ProcessMatcherRaw::Not { .. } => vec!["not"],
+            ProcessMatcherRaw::Parent { .. } => vec!["parent"],
+            ProcessMatcherRaw::Ancestor { .. } => vec!["ancestor", "depth"],
+            ProcessMatcherRaw::Pidfile { .. } => [&["pidfile", "exe_path"], PatternOpts::KEYS].concat(),
+            ProcessMatcherRaw::Pattern { pattern, .. } => [&[pattern.key()], PatternOpts::KEYS].concat(),
+            ProcessMatcherRaw::Owner { owner, .. } => vec![owner.key(), "effective"],
+            ProcessMatcherRaw::CwdUnder { .. } => vec!["cwd_under"],
+            ProcessMatcherRaw::Env { .. } => [&["env"], PatternOpts::KEYS].concat(),
+            ProcessMatcherRaw::Args { .. } => [&["args", "index"], PatternOpts::KEYS].concat(),
+            ProcessMatcherRaw::Listening { .. } => vec!["listening"],
+        }
+    }
+}
+
+/// Options controlling how the pattern of a matcher is parsed and compared
+#[derive(Deserialize, Clone, Debug, Default)]
+struct PatternOpts {
+    regex: Option<bool>,
+    glob: Option<bool>,
+    mode: Option<MatchMode>,
+    ignore_case: Option<bool>,
+}
+
+/// Kind of pattern selected with the `regex` and `glob` flags
+enum PatternKind {
+    Substring,
+    Regex,
+    Glob,
+}
+
+impl PatternOpts {
+    const KEYS: &'static [&'static str] = &["regex", "glob", "mode", "ignore_case"];
+
+    fn kind(&self) -> Result<PatternKind, de::value::Error> {
+        match (self.regex.unwrap_or_default(), self.glob.unwrap_or_default()) {
+            (true, true) => Err(de::Error::custom("`regex` and `glob` cannot be used together")),
+            (true, false) => Ok(PatternKind::Regex),
+            (false, true) if self.mode.is_some() => {
+                Err(de::Error::custom("`mode` cannot be used with glob patterns"))
+            }
+            (false, true) => Ok(PatternKind::Glob),
+            (false, false) => Ok(PatternKind::Substring),
+        }
+    }
+
+    fn string(&self, raw: String) -> Result<StrPattern, de::value::Error> {
+        Ok(StrPattern::new(raw, self.mode.unwrap_or_default(), self.ignore_case.unwrap_or_default()))
+    }
+
+    fn regex(&self, raw: String) -> Result<Regex, de::value::Error> {
+        parse_regex(raw, self.mode.unwrap_or_default(), self.ignore_case.unwrap_or_default())
+    }
+
+    fn glob(&self, raw: String, path: bool) -> Result<Glob, de::value::Error> {
+        parse_glob(raw, path, self.ignore_case.unwrap_or_default())
+    }
+}
+
+impl TryFrom<toml::Table> for ProcessMatcher {
+    type Error = de::value::Error;
+
+    fn try_from(table: toml::Table) -> Result<Self, Self::Error> {
+        let raw = ProcessMatcherRaw::deserialize(toml::Value::Table(table.clone())).map_err(de::Error::custom)?;
+
+        let keys = raw.keys();
+        if let Some(key) = table.keys().find(|key| !keys.contains(&key.as_str())) {
+            return Err(de::Error::custom(format!(
+                "unknown key `{key}` in matcher, expected one of: {}", keys.join(", ")
+            )));
+        }
+        raw.try_into()
+    }
 }
 
 //NOTE: help from https://users.rust-lang.org/t/serde-deserializing-a-generic-enum/117560
@@ -44,16 +252,123 @@ impl TryFrom<ProcessMatcherRaw> for ProcessMatcher {
     type Error =  de::value::Error;
 
     fn try_from(raw: ProcessMatcherRaw) -> Result<Self, Self::Error> {
-        if raw.regex.is_some_and(|x| x)  {
-            let pattern = convert_pattern(raw.pattern, parse_regex)?;
-            Ok(ProcessMatcher::RegexPattern(pattern))
-        } else {
-            let pattern = convert_pattern(raw.pattern, Ok)?;
-            Ok(ProcessMatcher::StringPattern(pattern))
+        match raw {
+            ProcessMatcherRaw::All { all } => Ok(ProcessMatcher::All(all)),
+            ProcessMatcherRaw::Any { any } => Ok(ProcessMatcher::Any(any)),
+            ProcessMatcherRaw::Not { not } => Ok(ProcessMatcher::Not(not)),
+            ProcessMatcherRaw::Parent { parent } => Ok(ProcessMatcher::Ancestor {
+                matcher: parent,
+                depth: Some(1),
+            }),
+            ProcessMatcherRaw::Ancestor { ancestor, depth } => {
+                if depth == Some(0) {
+                    return Err(de::Error::custom("ancestor depth must be at least 1"));
+                }
+                Ok(ProcessMatcher::Ancestor { matcher: ancestor, depth })
+            }
+            ProcessMatcherRaw::Pattern { pattern, opts } => {
+                let matcher = match opts.kind()? {
+                    PatternKind::Regex => {
+                        let pattern = convert_pattern(pattern, |s| opts.regex(s))?;
+                        ProcessMatcher::RegexPattern(pattern)
+                    }
+                    PatternKind::Glob => {
+                        let path = pattern.is_path();
+                        let pattern = convert_pattern(pattern, |s| opts.glob(s, path))?;
+                        ProcessMatcher::GlobPattern(pattern)
+                    }
+                    PatternKind::Substring => {
+                        let pattern = convert_pattern(pattern, |s| opts.string(s))?;
+                        ProcessMatcher::StringPattern(pattern)
+                    }
+                };
+                if exceeds_comm_len(&matcher) {
+                    warn!(
+                        "<{}>: pattern is longer than the {} bytes kernel process name and can never \
+                        match it, only processes whose exe or argv[0] has the full name will match",
+                        matcher, COMM_LEN
+                    );
+                }
+                Ok(matcher)
+            }
+            ProcessMatcherRaw::Owner { owner, effective } => {
+                let owner = match owner {
+                    OwnerRaw::User(name) => Owner::User { uid: resolve_user(&name)?, effective },
+                    OwnerRaw::Uid(uid) => Owner::User { uid, effective },
+                    OwnerRaw::Group(name) => Owner::Group { gid: resolve_group(&name)?, effective },
+                    OwnerRaw::Gid(gid) => Owner::Group { gid, effective },
+                };
+                Ok(ProcessMatcher::Owner(owner))
+            }
+            ProcessMatcherRaw::CwdUnder { cwd_under } => Ok(ProcessMatcher::CwdUnder(cwd_under)),
+            ProcessMatcherRaw::Env { env, opts } => {
+                let kind = opts.kind()?;
+
+                // each variable is a matcher of its own, they all have to match
+                let mut vars = env
+                    .into_iter()
+                    .map(|(key, value)| match value {
+                        EnvValueRaw::Set(set) => {
+                            let present = ProcessMatcher::EnvSet(key);
+                            Ok(if set { present } else { ProcessMatcher::Not(Box::new(present)) })
+                        }
+                        EnvValueRaw::Value(v) => match kind {
+                            PatternKind::Regex => Ok(PatternIn::Env(key, opts.regex(v)?).into()),
+                            PatternKind::Glob => Ok(PatternIn::Env(key, opts.glob(v, false)?).into()),
+                            PatternKind::Substring => Ok(PatternIn::Env(key, opts.string(v)?).into()),
+                        },
+                    })
+                    .collect::<Result<Vec<ProcessMatcher>, Self::Error>>()?;
+
+                match vars.len() {
+                    0 => Err(de::Error::custom("env matcher needs at least one variable")),
+                    1 => Ok(vars.remove(0)),
+                    _ => Ok(ProcessMatcher::All(vars)),
+                }
+            }
+            ProcessMatcherRaw::Pidfile { pidfile, exe_path, opts } => {
+                let pidfile = ProcessMatcher::Pidfile(Pidfile::new(pidfile));
+                let Some(exe_path) = exe_path else {
+                    return Ok(pidfile);
+                };
+
+                // the exe check guards against a stale pidfile whose pid was reused
+                let exe = match opts.kind()? {
+                    PatternKind::Regex => PatternIn::ExePath(opts.regex(exe_path)?).into(),
+                    PatternKind::Glob => PatternIn::ExePath(opts.glob(exe_path, true)?).into(),
+                    PatternKind::Substring => PatternIn::ExePath(opts.string(exe_path)?).into(),
+                };
+                Ok(ProcessMatcher::All(vec![pidfile, exe]))
+            }
+            ProcessMatcherRaw::Args { args, index, opts } => {
+                let position = index.map_or(ArgPosition::Any, ArgPosition::Index);
+                match opts.kind()? {
+                    PatternKind::Regex => Ok(PatternIn::Arg(position, opts.regex(args)?).into()),
+                    PatternKind::Glob => Ok(PatternIn::Arg(position, opts.glob(args, false)?).into()),
+                    PatternKind::Substring => Ok(PatternIn::Arg(position, opts.string(args)?).into()),
+                }
+            }
+            ProcessMatcherRaw::Listening { listening } => Ok(ProcessMatcher::Listening(Listening::new(listening))),
         }
     }
 }
 
+fn resolve_user(name: &str) -> Result<u32, de::value::Error> {
+    Users::new_with_refreshed_list()
+        .iter()
+        .find(|u| u.name() == name)
+        .map(|u| **u.id())
+        .ok_or_else(|| de::Error::custom(format!("unknown user: {name}")))
+}
+
+fn resolve_group(name: &str) -> Result<u32, de::value::Error> {
+    Groups::new_with_refreshed_list()
+        .iter()
+        .find(|g| g.name() == name)
+        .map(|g| **g.id())
+        .ok_or_else(|| de::Error::custom(format!("unknown group: {name}")))
+}
+
 fn convert_pattern<F, P, E>(raw: PatternInRaw, convert: F) -> Result<PatternIn<P>, E>
 where
     F: FnOnce(String) -> Result<P, E>
@@ -62,22 +377,250 @@ where
         PatternInRaw::ExePath(s) => PatternIn::ExePath(convert(s)?),
         PatternInRaw::Cmdline(s) => PatternIn::Cmdline(convert(s)?),
         PatternInRaw::Name(s) => PatternIn::Name(convert(s)?),
+        PatternInRaw::Argv0(s) => PatternIn::Arg(ArgPosition::Index(0), convert(s)?),
+        PatternInRaw::Cwd(s) => PatternIn::Cwd(convert(s)?),
+        PatternInRaw::Cgroup(s) => PatternIn::Cgroup(CgroupPart::Path, convert(s)?),
+        PatternInRaw::SystemdUnit(s) => PatternIn::Cgroup(CgroupPart::SystemdUnit, convert(s)?),
+        PatternInRaw::SystemdSlice(s) => PatternIn::Cgroup(CgroupPart::SystemdSlice, convert(s)?),
     })
 }
 
-fn parse_regex(raw: String) -> Result<Regex, de::value::Error> {
-    raw.parse::<Regex>().map_err(de::Error::custom)
+/// Whether a `name` pattern only matches names longer than the kernel process name limit
+fn exceeds_comm_len(matcher: &ProcessMatcher) -> bool {
+    let min_len = match matcher {
+        ProcessMatcher::StringPattern(PatternIn::Name(p)) => Some(p.pattern.len()),
+        ProcessMatcher::RegexPattern(PatternIn::Name(r)) => regex_min_len(r.as_str()),
+        ProcessMatcher::GlobPattern(PatternIn::Name(g)) => regex_min_len(g.0.glob().regex()),
+        _ => None,
+    };
+    min_len.is_some_and(|len| len > COMM_LEN)
+}
+
+/// Minimum length in bytes of the strings matched by a regex.
+/// Glob regexes match bytes, they are parsed without the utf-8 restriction.
+fn regex_min_len(regex: &str) -> Option<usize> {
+    regex_syntax::ParserBuilder::new()
+        .utf8(false)
+        .build()
+        .parse(regex)
+        .ok()?
+        .properties()
+        .minimum_len()
+}
+
+fn parse_regex(raw: String, mode: MatchMode, ignore_case: bool) -> Result<Regex, de::value::Error> {
+    let anchored = match mode {
+        MatchMode::Exact => format!("^(?:{raw})$"),
+        MatchMode::Prefix => format!("^(?:{raw})"),
+        MatchMode::Suffix => format!("(?:{raw})$"),
+        MatchMode::Contains => raw,
+    };
+    RegexBuilder::new(&anchored)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(de::Error::custom)
+}
+
+fn parse_glob(raw: String, path: bool, ignore_case: bool) -> Result<Glob, de::value::Error> {
+    let glob = if path { Glob::path(&raw, ignore_case) } else { Glob::new(&raw, ignore_case) };
+    glob.map_err(de::Error::custom)
+}
+
+/// How a string pattern is compared to the target
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    Exact,
+    Prefix,
+    Suffix,
+    #[default]
+    Contains,
+}
+
+/// A plain string pattern
+#[derive(Clone, Debug)]
+pub struct StrPattern {
+    pattern: String,
+    mode: MatchMode,
+    ignore_case: bool,
+}
+
+impl StrPattern {
+    pub fn new(pattern: impl Into<String>, mode: MatchMode, ignore_case: bool) -> Self {
+        let pattern = pattern.into();
+        Self {
+            pattern: if ignore_case { pattern.to_lowercase() } else { pattern },
+            mode,
+            ignore_case,
+        }
+    }
+
+    pub fn is_match(&self, hay: impl AsRef<[u8]>) -> bool {
+        let folded;
+        let hay = if self.ignore_case {
+            folded = String::from_utf8_lossy(hay.as_ref()).to_lowercase();
+            folded.as_bytes()
+        } else {
+            hay.as_ref()
+        };
+        let pattern = self.pattern.as_bytes();
+
+        match self.mode {
+            MatchMode::Exact => hay == pattern,
+            MatchMode::Prefix => hay.starts_with(pattern),
+            MatchMode::Suffix => hay.ends_with(pattern),
+            MatchMode::Contains => memmem::find(hay, pattern).is_some(),
+        }
+    }
+}
+
+impl From<String> for StrPattern {
+    fn from(pattern: String) -> Self {
+        Self::new(pattern, MatchMode::default(), false)
+    }
+}
+
+impl Display for StrPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.ignore_case {
+            write!(f, "(?i)")?;
+        }
+        match self.mode {
+            MatchMode::Exact => write!(f, "={}", self.pattern),
+            MatchMode::Prefix => write!(f, "{}*", self.pattern),
+            MatchMode::Suffix => write!(f, "*{}", self.pattern),
+            MatchMode::Contains => write!(f, "{}", self.pattern),
+        }
+    }
+}
+
+/// A shell like glob pattern. Unlike string and regex patterns a glob has to match the
+/// whole target: `/opt/*/bin/server` does not match `/opt/app/bin/server-old`.
+/// On paths (exe path, cwd and cgroup) `*` stays within a path component and `**` crosses
+/// directories, on the other targets `*` also matches `/`.
+#[derive(Clone, Debug)]
+pub struct Glob(globset::GlobMatcher);
+
+impl Glob {
+    /// Glob matched against any text like a command line, `*` also matches `/`
+    pub fn new(glob: &str, ignore_case: bool) -> Result<Self, globset::Error> {
+        Self::build(glob, ignore_case, false)
+    }
+
+    /// Glob matched against a path, `*` stays within a path component
+    pub fn path(glob: &str, ignore_case: bool) -> Result<Self, globset::Error> {
+        Self::build(glob, ignore_case, true)
+    }
+
+    fn build(glob: &str, ignore_case: bool, literal_separator: bool) -> Result<Self, globset::Error> {
+        let glob = globset::GlobBuilder::new(glob)
+            .case_insensitive(ignore_case)
+            .literal_separator(literal_separator)
+            .build()?;
+        Ok(Self(glob.compile_matcher()))
+    }
+
+    pub fn is_match(&self, hay: impl AsRef<std::path::Path>) -> bool {
+        self.0.is_match(hay)
+    }
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.glob().fmt(f)
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
-#[serde(try_from = "ProcessMatcherRaw")]
+#[serde(try_from = "toml::Table")]
 pub enum ProcessMatcher {
-    StringPattern(PatternIn<String>),
-    RegexPattern(PatternIn<Regex>)
+    StringPattern(PatternIn<StrPattern>),
+    RegexPattern(PatternIn<Regex>),
+    GlobPattern(PatternIn<Glob>),
+
+    /// matches when all the inner matchers match
+    All(Vec<ProcessMatcher>),
+
+    /// matches when at least one of the inner matchers matches
+    Any(Vec<ProcessMatcher>),
+
+    /// matches when the inner matcher does not match
+    Not(Box<ProcessMatcher>),
+
+    /// matches the user or group owning the process
+    Owner(Owner),
+
+    /// matches when one of the process ancestors matches the inner matcher, looking up to
+    /// `depth` levels up the process tree (1 is the direct parent), or up to the root
+    Ancestor {
+        matcher: Box<ProcessMatcher>,
+        depth: Option<usize>,
+    },
+
+    /// matches when the process working directory is the given path or one of its subdirectories
+    CwdUnder(PathBuf),
+
+    /// matches when the environment variable is set, whatever its value
+    EnvSet(String),
+
+    /// matches the process whose pid is written in a pidfile
+    Pidfile(Pidfile),
+
+    /// matches the processes holding a socket listening on a port
+    Listening(Listening),
+}
+
+impl ProcessMatcher {
+    /// Extends `kind` with the process data needed to evaluate this matcher.
+    /// Used to only refresh costly data when some profile needs it.
+    pub fn refresh_kind(&self, kind: ProcessRefreshKind) -> ProcessRefreshKind {
+        match self {
+            Self::StringPattern(PatternIn::Env(..))
+            | Self::RegexPattern(PatternIn::Env(..))
+            | Self::GlobPattern(PatternIn::Env(..))
+            | Self::EnvSet(_) => kind.with_environ(UpdateKind::OnlyIfNotSet),
+            Self::Owner(_) => kind.with_user(UpdateKind::Always),
+            Self::All(matchers) | Self::Any(matchers) => {
+                matchers.iter().fold(kind, |kind, m| m.refresh_kind(kind))
+            }
+            Self::Not(m) | Self::Ancestor { matcher: m, .. } => m.refresh_kind(kind),
+            Self::StringPattern(_)
+            | Self::RegexPattern(_)
+            | Self::GlobPattern(_)
+            | Self::CwdUnder(_)
+            | Self::Pidfile(_)
+            | Self::Listening(_) => kind,
+        }
+    }
+
+    /// Refreshes the data the matcher reads outside of the process table, like pidfiles.
+    /// Called once per refresh, before matching processes.
+    pub fn refresh(&mut self) {
+        match self {
+            Self::Pidfile(pidfile) => pidfile.refresh(),
+            Self::Listening(listening) => listening.refresh(),
+            Self::All(matchers) | Self::Any(matchers) => {
+                matchers.iter_mut().for_each(ProcessMatcher::refresh)
+            }
+            Self::Not(m) | Self::Ancestor { matcher: m, .. } => m.refresh(),
+            Self::StringPattern(_)
+            | Self::RegexPattern(_)
+            | Self::GlobPattern(_)
+            | Self::Owner(_)
+            | Self::CwdUnder(_)
+            | Self::EnvSet(_) => {}
+        }
+    }
 }
 
 impl From<PatternIn<String>> for ProcessMatcher {
     fn from(value: PatternIn<String>) -> Self {
+        Self::StringPattern(value.map(StrPattern::from))
+    }
+}
+
+impl From<PatternIn<StrPattern>> for ProcessMatcher {
+    fn from(value: PatternIn<StrPattern>) -> Self {
         Self::StringPattern(value)
     }
 }
@@ -88,6 +631,12 @@ impl From<PatternIn<Regex>> for ProcessMatcher {
     }
 }
 
+impl From<PatternIn<Glob>> for ProcessMatcher {
+    fn from(value: PatternIn<Glob>) -> Self {
+        Self::GlobPattern(value)
+    }
+}
+
 impl Display for ProcessMatcher {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -95,53 +644,433 @@ impl Display for ProcessMatcher {
                 p.fmt(f)
             },
             Self::RegexPattern(p) => {p.fmt(f)},
+            Self::GlobPattern(p) => {p.fmt(f)},
+            Self::All(matchers) => {
+                write!(f, "all(")?;
+                fmt_matchers(matchers, f)?;
+                write!(f, ")")
+            },
+            Self::Any(matchers) => {
+                write!(f, "any(")?;
+                fmt_matchers(matchers, f)?;
+                write!(f, ")")
+            },
+            Self::Not(m) => {
+                write!(f, "not({})", m)
+            },
+            Self::Owner(o) => o.fmt(f),
+            Self::Ancestor { matcher, depth: Some(1) } => {
+                write!(f, "parent({})", matcher)
+            },
+            Self::Ancestor { matcher, depth: Some(depth) } => {
+                write!(f, "ancestor<{}>({})", depth, matcher)
+            },
+            Self::Ancestor { matcher, depth: None } => {
+                write!(f, "ancestor({})", matcher)
+            },
+            Self::CwdUnder(path) => {
+                write!(f, "cwd_under[{}]", path.display())
+            },
+            Self::EnvSet(key) => {
+                write!(f, "env[{}]", key)
+            },
+            Self::Pidfile(pidfile) => pidfile.fmt(f),
+            Self::Listening(listening) => listening.fmt(f),
+        }
+    }
+}
+
+fn fmt_matchers(matchers: &[ProcessMatcher], f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for (i, m) in matchers.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", m)?;
+    }
+    Ok(())
+}
+
+
+/// A pidfile written by a daemon. The file is read again on every refresh, a missing
+/// file or a pid that is not running does not match any process.
+#[derive(Clone, Debug)]
+pub struct Pidfile {
+    path: PathBuf,
+
+    /// pid read on the last refresh
+    pid: Option<Pid>,
+}
+
+impl Pidfile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), pid: None }
+    }
+
+    pub fn pid(&self) -> Option<Pid> {
+        self.pid
+    }
+
+    fn refresh(&mut self) {
+        self.pid = match fs::read_to_string(&self.path) {
+            Ok(content) => parse_pid(&content),
+            Err(e) => {
+                debug!("pidfile {}: {}", self.path.display(), e);
+                None
+            }
+        };
+    }
+}
+
+/// Parses the content of a pidfile, the pid is the first word of the file
+fn parse_pid(content: &str) -> Option<Pid> {
+    content
+        .split_whitespace()
+        .next()?
+        .parse::<usize>()
+        .ok()
+        .filter(|pid| *pid > 0)
+        .map(Pid::from)
+}
+
+impl Display for Pidfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pidfile[{}]", self.path.display())
+    }
+}
+
+/// A listening socket. The socket tables are read again on every refresh and the processes
+/// holding the sockets are found from the socket links of their file descriptors.
+#[derive(Clone, Debug)]
+pub struct Listening {
+    port: SocketPort,
+
+    /// processes holding the sockets listening on the port on the last refresh
+    pids: HashSet<Pid>,
+
+    /// a failure to read the sockets or the processes holding them was reported
+    error_reported: bool,
+}
+
+impl Listening {
+    pub fn new(port: SocketPort) -> Self {
+        Self { port, pids: HashSet::new(), error_reported: false }
+    }
+
+    /// Resolves the processes holding the listening sockets. Every process holding a socket
+    /// matches, like the workers of a pre-fork server sharing the socket of their parent.
+    fn refresh(&mut self) {
+        self.pids.clear();
+        if let Err(e) = self.find_holders() {
+            if !self.error_reported {
+                warn!("<{}>: {}", self, e);
+                self.error_reported = true;
+            }
+        }
+    }
+
+    /// Fills `pids` with the holders of the listening sockets, errors when some of them may
+    /// be missing
+    fn find_holders(&mut self) -> Result<(), String> {
+        let inodes: HashSet<u64> = procfs::sockets(self.port.proto)
+            .map_err(|e| format!("cannot read the socket tables: {}", e))?
+            .iter()
+            .filter(|s| s.is_listening() && s.local_port == self.port.port)
+            .map(|s| s.inode)
+            .collect();
+        // the file descriptors are only read when something listens on the port
+        if inodes.is_empty() {
+            return Ok(());
+        }
+        let pids = procfs::pids().map_err(|e| format!("cannot list the processes: {}", e))?;
+
+        let mut denied = vec![];
+        for pid in pids {
+            match procfs::socket_inodes(pid) {
+                Ok(held) if !held.is_disjoint(&inodes) => {
+                    self.pids.insert(pid);
+                }
+                Ok(_) => {}
+                // exited since the processes were listed
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => denied.push((pid, e)),
+            }
+        }
+        match denied.first() {
+            Some((pid, e)) => Err(format!(
+                "the socket may be held by one of the {} processes that cannot be inspected \
+                (pid {}: {}), run pswatch as root or with CAP_SYS_PTRACE to match it",
+                denied.len(),
+                pid,
+                e
+            )),
+            None => Ok(()),
         }
     }
+
+    fn matches(&self, pid: Pid) -> bool {
+        self.pids.contains(&pid)
+    }
+}
+
+impl Display for Listening {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "listening[{}]", self.port)
+    }
+}
+
+/// Process owner. User and group names are resolved to ids when loading the config.
+/// When `effective` is set the effective ids are used instead of the real ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Owner {
+    User { uid: u32, effective: bool },
+    Group { gid: u32, effective: bool },
 }
 
+impl Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Owner::User { uid, effective } => {
+                write!(f, "{}uid[{}]", if *effective { "e" } else { "" }, uid)
+            },
+            Owner::Group { gid, effective } => {
+                write!(f, "{}gid[{}]", if *effective { "e" } else { "" }, gid)
+            },
+        }
+    }
+}
+
+impl MatchBy<Owner> for sysinfo::Process {
+    fn match_by(&self, matcher: &Owner, _info: &sysinfo::System) -> bool {
+        match *matcher {
+            Owner::User { uid, effective: false } => self.user_id().is_some_and(|id| **id == uid),
+            Owner::User { uid, effective: true } => {
+                self.effective_user_id().is_some_and(|id| **id == uid)
+            }
+            Owner::Group { gid, effective: false } => self.group_id().is_some_and(|id| *id == gid),
+            Owner::Group { gid, effective: true } => {
+                self.effective_group_id().is_some_and(|id| *id == gid)
+            }
+        }
+    }
+}
 
 #[derive(Deserialize, Clone, Debug)]
 pub enum PatternIn<P> {
     ExePath(P),
     Cmdline(P),
     Name(P),
+    Cwd(P),
+    Env(String, P),
+    Cgroup(CgroupPart, P),
+    Arg(ArgPosition, P),
+}
+
+impl<P> PatternIn<P> {
+    /// Converts the pattern while keeping what it is matched against
+    pub fn map<Q>(self, f: impl FnOnce(P) -> Q) -> PatternIn<Q> {
+        match self {
+            PatternIn::ExePath(p) => PatternIn::ExePath(f(p)),
+            PatternIn::Cmdline(p) => PatternIn::Cmdline(f(p)),
+            PatternIn::Name(p) => PatternIn::Name(f(p)),
+            PatternIn::Cwd(p) => PatternIn::Cwd(f(p)),
+            PatternIn::Env(key, p) => PatternIn::Env(key, f(p)),
+            PatternIn::Cgroup(part, p) => PatternIn::Cgroup(part, f(p)),
+            PatternIn::Arg(position, p) => PatternIn::Arg(position, f(p)),
+        }
+    }
+}
+
+/// Part of the cgroup path matched by a `PatternIn::Cgroup`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CgroupPart {
+    /// the full cgroup path
+    Path,
+
+    /// the innermost systemd service or scope
+    SystemdUnit,
+
+    /// the innermost systemd slice
+    SystemdSlice,
+}
+
+impl CgroupPart {
+    fn select(self, cgroup: &str) -> Option<&str> {
+        match self {
+            CgroupPart::Path => Some(cgroup),
+            CgroupPart::SystemdUnit => procfs::systemd_unit(cgroup),
+            CgroupPart::SystemdSlice => procfs::systemd_slice(cgroup),
+        }
+    }
+}
+
+/// Command line arguments matched by a `PatternIn::Arg`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ArgPosition {
+    /// any argument, the program name `argv[0]` excluded
+    Any,
+
+    /// the argument at the given index, 0 is the program name
+    Index(usize),
+}
+
+impl ArgPosition {
+    fn select(self, cmd: &[String]) -> &[String] {
+        match self {
+            ArgPosition::Any => cmd.get(1..).unwrap_or_default(),
+            ArgPosition::Index(i) => cmd.get(i..=i).unwrap_or_default(),
+        }
+    }
 }
 
+impl Display for ArgPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgPosition::Any => write!(f, "args"),
+            ArgPosition::Index(i) => write!(f, "argv{i}"),
+        }
+    }
+}
 
-impl MatchProcByPattern<String> for sysinfo::Process {
-    fn matches_exe(&self, pattern: String) -> bool {
-        let finder = memmem::Finder::new(&pattern);
+impl Display for CgroupPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let part = match self {
+            CgroupPart::Path => "cgroup",
+            CgroupPart::SystemdUnit => "systemd_unit",
+            CgroupPart::SystemdSlice => "systemd_slice",
+        };
+        write!(f, "{part}")
+    }
+}
+
+
+impl MatchProcByPattern<StrPattern> for sysinfo::Process {
+    fn matches_exe(&self, pattern: &StrPattern) -> bool {
         self.exe()
-            .and_then(|exe_name| finder.find(exe_name.as_os_str().as_bytes()))
-            .is_some()
+            .is_some_and(|exe_name| pattern.is_match(exe_name.as_os_str().as_bytes()))
+    }
+
+    fn matches_cmdline(&self, pattern: &StrPattern) -> bool {
+        pattern.is_match(self.cmd().join(" "))
+    }
+
+    fn matches_name(&self, pattern: &StrPattern) -> bool {
+        pattern.is_match(self.name()) || full_name(self).is_some_and(|name| pattern.is_match(name))
+    }
+
+    fn matches_cwd(&self, pattern: &StrPattern) -> bool {
+        self.cwd()
+            .is_some_and(|cwd| pattern.is_match(cwd.as_os_str().as_bytes()))
+    }
+
+    fn matches_env(&self, key: &str, pattern: &StrPattern) -> bool {
+        env_var(self, key).is_some_and(|value| pattern.is_match(value))
     }
 
-    fn matches_cmdline(&self, pattern: String) -> bool {
-        let finder = memmem::Finder::new(&pattern);
-        finder.find(self.cmd().join(" ").as_bytes()).is_some()
+    fn matches_cgroup(&self, part: CgroupPart, pattern: &StrPattern) -> bool {
+        procfs::cgroup(self.pid())
+            .is_ok_and(|cgroup| part.select(&cgroup).is_some_and(|hay| pattern.is_match(hay)))
     }
 
-    fn matches_name(&self, pattern: String) -> bool {
-        self.name().contains(&pattern)
+    fn matches_arg(&self, position: ArgPosition, pattern: &StrPattern) -> bool {
+        position.select(self.cmd()).iter().any(|arg| pattern.is_match(arg))
     }
 }
 
 impl MatchProcByPattern<Regex> for sysinfo::Process {
-    fn matches_exe(&self, pattern: Regex) -> bool {
+    fn matches_exe(&self, pattern: &Regex) -> bool {
         self.exe()
         .and_then(|exe_name| exe_name.as_os_str().to_str())
         .is_some_and(|hay| pattern.is_match(hay))
     }
 
-    fn matches_cmdline(&self, pattern: Regex) -> bool {
+    fn matches_cmdline(&self, pattern: &Regex) -> bool {
         pattern.is_match(&self.cmd().join(" "))
     }
 
-    fn matches_name(&self, pattern: Regex) -> bool {
-        pattern.is_match(self.name())
+    fn matches_name(&self, pattern: &Regex) -> bool {
+        pattern.is_match(self.name()) || full_name(self).is_some_and(|name| pattern.is_match(name))
+    }
+
+    fn matches_cwd(&self, pattern: &Regex) -> bool {
+        self.cwd()
+        .and_then(|cwd| cwd.as_os_str().to_str())
+        .is_some_and(|hay| pattern.is_match(hay))
+    }
+
+    fn matches_env(&self, key: &str, pattern: &Regex) -> bool {
+        env_var(self, key).is_some_and(|value| pattern.is_match(value))
+    }
+
+    fn matches_cgroup(&self, part: CgroupPart, pattern: &Regex) -> bool {
+        procfs::cgroup(self.pid())
+            .is_ok_and(|cgroup| part.select(&cgroup).is_some_and(|hay| pattern.is_match(hay)))
+    }
+
+    fn matches_arg(&self, position: ArgPosition, pattern: &Regex) -> bool {
+        position.select(self.cmd()).iter().any(|arg| pattern.is_match(arg))
     }
 }
 
+impl MatchProcByPattern<Glob> for sysinfo::Process {
+    fn matches_exe(&self, pattern: &Glob) -> bool {
+        self.exe().is_some_and(|exe| pattern.is_match(exe))
+    }
+
+    fn matches_cmdline(&self, pattern: &Glob) -> bool {
+        pattern.is_match(self.cmd().join(" "))
+    }
+
+    fn matches_name(&self, pattern: &Glob) -> bool {
+        pattern.is_match(self.name()) || full_name(self).is_some_and(|name| pattern.is_match(name))
+    }
+
+    fn matches_cwd(&self, pattern: &Glob) -> bool {
+        self.cwd().is_some_and(|cwd| pattern.is_match(cwd))
+    }
+
+    fn matches_env(&self, key: &str, pattern: &Glob) -> bool {
+        env_var(self, key).is_some_and(|value| pattern.is_match(value))
+    }
+
+    fn matches_cgroup(&self, part: CgroupPart, pattern: &Glob) -> bool {
+        procfs::cgroup(self.pid())
+            .is_ok_and(|cgroup| part.select(&cgroup).is_some_and(|hay| pattern.is_match(hay)))
+    }
+
+    fn matches_arg(&self, position: ArgPosition, pattern: &Glob) -> bool {
+        position.select(self.cmd()).iter().any(|arg| pattern.is_match(arg))
+    }
+}
+
+/// Full name of a process whose name was truncated by the kernel to `COMM_LEN` bytes.
+/// It is the basename of the exe or of `argv[0]` that extends the truncated name.
+fn full_name(proc: &sysinfo::Process) -> Option<&str> {
+    let name = proc.name();
+    if name.len() != COMM_LEN {
+        return None;
+    }
+
+    let exe = proc.exe().and_then(basename);
+    let argv0 = proc.cmd().first().and_then(|arg| basename(Path::new(arg)));
+    [exe, argv0]
+        .into_iter()
+        .flatten()
+        .find(|full| full.len() > COMM_LEN && full.starts_with(name))
+}
+
+fn basename(path: &Path) -> Option<&str> {
+    path.file_name().and_then(|n| n.to_str())
+}
+
+/// Value of the `key` environment variable of a process
+fn env_var<'a>(proc: &'a sysinfo::Process, key: &str) -> Option<&'a str> {
+    proc.environ().iter().find_map(|var| {
+        var.split_once('=')
+            .and_then(|(k, v)| (k == key).then_some(v))
+    })
+}
+
 impl<P> Display for PatternIn<P> where P: Display {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -154,6 +1083,18 @@ impl<P> Display for PatternIn<P> where P: Display {
             PatternIn::Name(p) => {
                 write!(f, "name[{}]", p)
             },
+            PatternIn::Cwd(p) => {
+                write!(f, "cwd[{}]", p)
+            },
+            PatternIn::Env(key, p) => {
+                write!(f, "env[{}={}]", key, p)
+            },
+            PatternIn::Cgroup(part, p) => {
+                write!(f, "{}[{}]", part, p)
+            },
+            PatternIn::Arg(position, p) => {
+                write!(f, "{}[{}]", position, p)
+            },
         }
     }
 }
@@ -163,21 +1104,295 @@ where
     sysinfo::Process: MatchProcByPattern<P>,
     P: Display
 {
-    fn match_by(&self, matcher: PatternIn<P>) -> bool {
+    fn match_by(&self, matcher: &PatternIn<P>, _info: &sysinfo::System) -> bool {
         match matcher {
             PatternIn::ExePath(pat) => self.matches_exe(pat),
             PatternIn::Cmdline(pat) => self.matches_cmdline(pat),
             PatternIn::Name(pat) => self.matches_name(pat),
+            PatternIn::Cwd(pat) => self.matches_cwd(pat),
+            PatternIn::Env(key, pat) => self.matches_env(key, pat),
+            PatternIn::Cgroup(part, pat) => self.matches_cgroup(*part, pat),
+            PatternIn::Arg(position, pat) => self.matches_arg(*position, pat),
         }
     }
 }
 
 impl MatchBy<ProcessMatcher> for sysinfo::Process {
-    fn match_by(&self, matcher: ProcessMatcher) -> bool {
+    fn match_by(&self, matcher: &ProcessMatcher, info: &sysinfo::System) -> bool {
         match matcher {
-            ProcessMatcher::StringPattern(pat) => self.match_by(pat),
-            ProcessMatcher::RegexPattern(pat) => self.match_by(pat),
+            ProcessMatcher::StringPattern(pat) => self.match_by(pat, info),
+            ProcessMatcher::RegexPattern(pat) => self.match_by(pat, info),
+            ProcessMatcher::GlobPattern(pat) => self.match_by(pat, info),
+            ProcessMatcher::All(matchers) => matchers.iter().all(|m| self.match_by(m, info)),
+            ProcessMatcher::Any(matchers) => matchers.iter().any(|m| self.match_by(m, info)),
+            ProcessMatcher::Not(m) => !self.match_by(m.as_ref(), info),
+            ProcessMatcher::Owner(o) => self.match_by(o, info),
+            ProcessMatcher::Ancestor { matcher, depth } => {
+                ancestors(self, info)
+                    .take(depth.unwrap_or(usize::MAX))
+                    .any(|p| p.match_by(matcher.as_ref(), info))
+            }
+            ProcessMatcher::CwdUnder(path) => self.cwd().is_some_and(|cwd| cwd.starts_with(path)),
+            ProcessMatcher::EnvSet(key) => env_var(self, key).is_some(),
+            ProcessMatcher::Pidfile(pidfile) => pidfile.pid() == Some(self.pid()),
+            ProcessMatcher::Listening(listening) => listening.matches(self.pid()),
         }
     }
 }
 
+/// Iterates over the ancestors of a process, starting with its direct parent
+fn ancestors<'a>(
+    proc: &'a sysinfo::Process,
+    info: &'a sysinfo::System,
+) -> impl Iterator<Item = &'a sysinfo::Process> {
+    std::iter::successors(Some(proc), |p| p.parent().and_then(|ppid| info.process(ppid)))
+        .skip(1)
+        // guard against loops in a corrupted process table
+        .take(info.processes().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Profile {
+        matching: ProcessMatcher,
+    }
+
+    fn parse(s: &str) -> ProcessMatcher {
+        toml::from_str::<Profile>(s).unwrap().matching
+    }
+
+    #[test]
+    fn parse_composite_matcher() {
+        let m = parse(r#"
+            matching = { all = [
+                { name = "python" },
+                { cmdline = "jupyter" },
+                { not = { exe_path = "^/usr/lib/venv-tools", regex = true } },
+            ] }
+        "#);
+        assert!(matches!(&m, ProcessMatcher::All(v) if v.len() == 3));
+        assert_eq!(
+            m.to_string(),
+            "all(name[python], cmdline[jupyter], not(exe_path[^/usr/lib/venv-tools]))"
+        );
+
+        let m = parse(r#"matching = { any = [{ name = "foo" }, { name = "ba.", regex = true }] }"#);
+        assert!(matches!(&m, ProcessMatcher::Any(v)
+            if matches!(v[1], ProcessMatcher::RegexPattern(PatternIn::Name(_)))));
+    }
+
+    #[test]
+    fn parse_unknown_keys() {
+        let res = toml::from_str::<Profile>(r#"matching = { name = "x", not = { name = "y" } }"#);
+        assert!(res.is_err_and(|e| e.message().contains("unknown key `name`")));
+
+        let res = toml::from_str::<Profile>(r#"matching = { name = "cargo", user = "root" }"#);
+        assert!(res.is_err_and(|e| e.message().contains("unknown key `user`")));
+
+        assert!(toml::from_str::<Profile>(r#"matching = { name = "a", cmdline = "b" }"#).is_err());
+        assert!(toml::from_str::<Profile>(r#"matching = { all = [{ name = "a", regx = true }] }"#).is_err());
+
+        let m = parse(r#"matching = { args = "-v", index = 1, mode = "exact", ignore_case = true }"#);
+        assert!(matches!(m, ProcessMatcher::StringPattern(PatternIn::Arg(ArgPosition::Index(1), _))));
+    }
+
+    #[test]
+    fn parse_owner_matcher() {
+        let m = parse(r#"matching = { user = "root" }"#);
+        assert!(matches!(m, ProcessMatcher::Owner(Owner::User { uid: 0, effective: false })));
+
+        let m = parse(r#"matching = { gid = 100, effective = true }"#);
+        assert!(matches!(m, ProcessMatcher::Owner(Owner::Group { gid: 100, effective: true })));
+        assert_eq!(m.to_string(), "egid[100]");
+
+        let res = toml::from_str::<Profile>(r#"matching = { user = "no_such_user_8c2f1" }"#);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn parse_ancestor_matcher() {
+        let m = parse(r#"matching = { parent = { name = "sshd" } }"#);
+        assert!(matches!(m, ProcessMatcher::Ancestor { depth: Some(1), .. }));
+        assert_eq!(m.to_string(), "parent(name[sshd])");
+
+        let m = parse(r#"matching = { ancestor = { name = "^code$", regex = true }, depth = 4 }"#);
+        assert_eq!(m.to_string(), "ancestor<4>(name[^code$])");
+
+        let m = parse(r#"matching = { all = [{ name = "node" }, { ancestor = { name = "code" } }] }"#);
+        assert_eq!(m.to_string(), "all(name[node], ancestor(name[code]))");
+
+        let res = toml::from_str::<Profile>(r#"matching = { ancestor = { name = "x" }, depth = 0 }"#);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn parse_cwd_matcher() {
+        let m = parse(r#"matching = { cwd = "monorepo" }"#);
+        assert!(matches!(m, ProcessMatcher::StringPattern(PatternIn::Cwd(_))));
+
+        let m = parse(r#"matching = { cwd_under = "/home/me/src/monorepo" }"#);
+        assert_eq!(m.to_string(), "cwd_under[/home/me/src/monorepo]");
+    }
+
+    #[test]
+    fn parse_env_matcher() {
+        let m = parse(r#"matching = { env = { JOB_KIND = "nightly" } }"#);
+        assert_eq!(m.to_string(), "env[JOB_KIND=nightly]");
+
+        let m = parse(r#"matching = { env = { JOB_KIND = "^night", VIRTUAL_ENV = true, CI = false }, regex = true }"#);
+        assert_eq!(m.to_string(), "all(not(env[CI]), env[JOB_KIND=^night], env[VIRTUAL_ENV])");
+        assert!(matches!(&m, ProcessMatcher::All(v)
+            if matches!(v[1], ProcessMatcher::RegexPattern(PatternIn::Env(..)))));
+
+        let kind = m.refresh_kind(ProcessRefreshKind::new());
+        assert!(matches!(kind.environ(), UpdateKind::OnlyIfNotSet));
+        let kind = parse(r#"matching = { name = "foo" }"#).refresh_kind(ProcessRefreshKind::new());
+        assert!(matches!(kind.environ(), UpdateKind::Never));
+
+        assert!(toml::from_str::<Profile>(r#"matching = { env = {} }"#).is_err());
+    }
+
+    #[test]
+    fn parse_cgroup_matcher() {
+        let m = parse(r#"matching = { systemd_unit = '^app-firefox-\d+\.scope$', regex = true }"#);
+        assert!(matches!(m, ProcessMatcher::RegexPattern(PatternIn::Cgroup(CgroupPart::SystemdUnit, _))));
+        assert_eq!(m.to_string(), r"systemd_unit[^app-firefox-\d+\.scope$]");
+
+        let m = parse(r#"matching = { cgroup = "user@1000.service/app.slice" }"#);
+        assert_eq!(m.to_string(), "cgroup[user@1000.service/app.slice]");
+    }
+
+    #[test]
+    fn parse_glob_matcher() {
+        let m = parse(r#"matching = { exe_path = "/opt/*/bin/server", glob = true }"#);
+        assert!(matches!(&m, ProcessMatcher::GlobPattern(PatternIn::ExePath(g))
+            if g.is_match("/opt/app/bin/server") && !g.is_match("/opt/app/bin/server-old")
+                && !g.is_match("/opt/app/v2/bin/server")));
+        assert_eq!(m.to_string(), "exe_path[/opt/*/bin/server]");
+
+        let m = parse(r#"matching = { exe_path = "/opt/**/server", glob = true }"#);
+        assert!(matches!(&m, ProcessMatcher::GlobPattern(PatternIn::ExePath(g))
+            if g.is_match("/opt/app/v2/bin/server") && g.is_match("/opt/server")));
+
+        let m = parse(r#"matching = { systemd_unit = "app-firefox-*.scope", glob = true }"#);
+        assert!(matches!(m, ProcessMatcher::GlobPattern(PatternIn::Cgroup(CgroupPart::SystemdUnit, _))));
+
+        let m = parse(r#"matching = { env = { VIRTUAL_ENV = "/home/*/venvs/*" }, glob = true }"#);
+        assert!(matches!(&m, ProcessMatcher::GlobPattern(PatternIn::Env(_, g))
+            if g.is_match("/home/me/venvs/app/v2")));
+
+        // command lines are not paths, `*` matches across `/`
+        let m = parse(r#"matching = { cmdline = "*jupyter*", glob = true }"#);
+        assert!(matches!(&m, ProcessMatcher::GlobPattern(PatternIn::Cmdline(g))
+            if g.is_match("/usr/bin/python3 -m jupyter notebook")));
+        let m = parse(r#"matching = { cmdline = "python*", glob = true }"#);
+        assert!(matches!(&m, ProcessMatcher::GlobPattern(PatternIn::Cmdline(g))
+            if g.is_match("python /home/me/app.py")));
+
+        assert!(toml::from_str::<Profile>(r#"matching = { name = "[", glob = true }"#).is_err());
+        assert!(toml::from_str::<Profile>(r#"matching = { name = "a", glob = true, regex = true }"#).is_err());
+    }
+
+    #[test]
+    fn parse_match_modes() {
+        let m = parse(r#"matching = { name = "cc", mode = "exact" }"#);
+        assert!(matches!(&m, ProcessMatcher::StringPattern(PatternIn::Name(p))
+            if p.is_match("cc") && !p.is_match("gcc") && !p.is_match("ccache")));
+        assert_eq!(m.to_string(), "name[=cc]");
+
+        let m = parse(r#"matching = { name = "CC", mode = "prefix", ignore_case = true }"#);
+        assert!(matches!(&m, ProcessMatcher::StringPattern(PatternIn::Name(p))
+            if p.is_match("ccache") && !p.is_match("gcc")));
+        assert_eq!(m.to_string(), "name[(?i)cc*]");
+
+        let m = parse(r#"matching = { name = "cc", mode = "suffix" }"#);
+        assert!(matches!(&m, ProcessMatcher::StringPattern(PatternIn::Name(p))
+            if p.is_match("gcc") && !p.is_match("ccache")));
+
+        let m = parse(r#"matching = { name = "c+|clang", regex = true, mode = "exact", ignore_case = true }"#);
+        assert!(matches!(&m, ProcessMatcher::RegexPattern(PatternIn::Name(r))
+            if r.is_match("CLANG") && r.is_match("cc") && !r.is_match("clang-check")));
+
+        let m = parse(r#"matching = { exe_path = "/USR/BIN/*", glob = true, ignore_case = true }"#);
+        assert!(matches!(&m, ProcessMatcher::GlobPattern(PatternIn::ExePath(g)) if g.is_match("/usr/bin/cc")));
+
+        assert!(toml::from_str::<Profile>(r#"matching = { name = "a*", glob = true, mode = "exact" }"#).is_err());
+        assert!(toml::from_str::<Profile>(r#"matching = { name = "a", mode = "fuzzy" }"#).is_err());
+    }
+
+    #[test]
+    fn parse_args_matcher() {
+        let m = parse(r#"matching = { args = "serve", index = 1, mode = "exact" }"#);
+        assert!(matches!(m, ProcessMatcher::StringPattern(PatternIn::Arg(ArgPosition::Index(1), _))));
+        assert_eq!(m.to_string(), "argv1[=serve]");
+
+        let m = parse(r#"matching = { args = "--gpu*", glob = true }"#);
+        assert!(matches!(m, ProcessMatcher::GlobPattern(PatternIn::Arg(ArgPosition::Any, _))));
+        assert_eq!(m.to_string(), "args[--gpu*]");
+
+        let m = parse(r#"matching = { argv0 = '^(/usr/bin/)?python3?$', regex = true }"#);
+        assert!(matches!(m, ProcessMatcher::RegexPattern(PatternIn::Arg(ArgPosition::Index(0), _))));
+        assert_eq!(m.to_string(), "argv0[^(/usr/bin/)?python3?$]");
+    }
+
+    #[test]
+    fn select_args() {
+        let cmd = ["ollama", "serve", "--gpu", "two words"].map(String::from);
+        assert_eq!(ArgPosition::Any.select(&cmd), &cmd[1..]);
+        assert_eq!(ArgPosition::Index(0).select(&cmd), ["ollama"]);
+        assert_eq!(ArgPosition::Index(3).select(&cmd), ["two words"]);
+        assert!(ArgPosition::Index(4).select(&cmd).is_empty());
+        assert!(ArgPosition::Any.select(&[]).is_empty());
+    }
+
+    #[test]
+    fn parse_listening_matcher() {
+        let m = parse(r#"matching = { listening = { proto = "udp", port = 51820 } }"#);
+        assert!(matches!(m, ProcessMatcher::Listening(_)));
+        assert_eq!(m.to_string(), "listening[udp:51820]");
+        // tcp by default
+        let m = parse(r#"matching = { listening = { port = 5432 } }"#);
+        assert_eq!(m.to_string(), "listening[tcp:5432]");
+
+        assert!(toml::from_str::<Profile>(r#"matching = { listening = { port = 70000 } }"#).is_err());
+        assert!(toml::from_str::<Profile>(r#"matching = { listening = { proto = "sctp", port = 1 } }"#).is_err());
+    }
+
+    #[test]
+    fn parse_pidfile_matcher() {
+        let m = parse(r#"matching = { pidfile = "/run/foo.pid" }"#);
+        assert!(matches!(m, ProcessMatcher::Pidfile(_)));
+        assert_eq!(m.to_string(), "pidfile[/run/foo.pid]");
+
+        let m = parse(r#"matching = { pidfile = "/run/foo.pid", exe_path = "/usr/sbin/foo", mode = "exact" }"#);
+        assert_eq!(m.to_string(), "all(pidfile[/run/foo.pid], exe_path[=/usr/sbin/foo])");
+
+        assert_eq!(parse_pid("4242\n"), Some(Pid::from(4242)));
+        assert_eq!(parse_pid("  4242 extra"), Some(Pid::from(4242)));
+        assert_eq!(parse_pid(""), None);
+        assert_eq!(parse_pid("0"), None);
+        assert_eq!(parse_pid("foo"), None);
+    }
+
+    #[test]
+    fn name_pattern_length() {
+        assert!(exceeds_comm_len(&parse(r#"matching = { name = "my-long-daemon-name" }"#)));
+        assert!(!exceeds_comm_len(&parse(r#"matching = { name = "my-long-daemon-" }"#)));
+        assert!(exceeds_comm_len(&parse(r#"matching = { name = "^my-long-daemon-name$", regex = true }"#)));
+        assert!(!exceeds_comm_len(&parse(r#"matching = { name = "^my-long-daemon-.*$", regex = true }"#)));
+        assert!(exceeds_comm_len(&parse(r#"matching = { name = "my-long-*-daemon-name", glob = true }"#)));
+        assert!(!exceeds_comm_len(&parse(r#"matching = { cmdline = "my-long-daemon-name" }"#)));
+    }
+
+    #[test]
+    fn parse_invalid_nested_regex() {
+        let res = toml::from_str::<Profile>(r#"
+            matching = { any = [{ name = "(", regex = true }] }
+        "#);
+        assert!(res.is_err());
+    }
+}
+
@@ -1,10 +1,11 @@
-use std::{fmt::Display, time::Duration};
+use std::{collections::{HashMap, HashSet, VecDeque}, fmt::Display, ops::Add, sync::OnceLock, time::Duration};
 
 use crate::matching::{MatchBy, ProcessMatcher};
+use crate::procfs::{self, Proto, SocketPort};
 use crate::state::{ConditionMatcher, StateTracker};
-use log::{debug, log_enabled, trace};
-use serde::Deserialize;
-use sysinfo::{self, Pid, ProcessStatus};
+use log::{debug, log_enabled, trace, warn};
+use serde::{de, Deserialize};
+use sysinfo::{self, MemoryRefreshKind, Pid, ProcessRefreshKind, ProcessStatus, RefreshKind, ThreadKind};
 
 #[cfg(test)]
 use mock_instant::thread_local::Instant;
@@ -12,6 +13,14 @@ use mock_instant::thread_local::Instant;
 #[cfg(not(test))]
 use std::time::Instant;
 
+/// Environment variable set on the commands spawned by pswatch, it holds the pswatch PID.
+/// Being inherited, it marks the whole tree of processes started by a command.
+pub const SPAWNED_BY_ENV: &str = "PSWATCH_SPAWNED_BY";
+
+/// Environment variable holding the PID of the instance a command runs for, when the
+/// profile tracks every PID on its own
+pub const INSTANCE_PID_ENV: &str = "PSWATCH_PID";
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ProcState {
     NeverSeen,
@@ -28,6 +37,9 @@ pub struct ProcLifetime {
     prev_state: Option<ProcState>,
     state: ProcState,
     state_exit: bool,
+
+    /// times the process reappeared or one of its pids was replaced
+    restarts: VecDeque<Instant>,
 }
 
 impl ProcLifetime {
@@ -40,6 +52,15 @@ impl ProcLifetime {
             prev_state: None,
             state: ProcState::NeverSeen,
             state_exit: false,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// Records `count` restarts at `time` and forgets the ones older than `retention`
+    fn record_restarts(&mut self, count: usize, time: Instant, retention: Duration) {
+        self.restarts.extend(std::iter::repeat_n(time, count));
+        while self.restarts.front().is_some_and(|t| time.duration_since(*t) > retention) {
+            self.restarts.pop_front();
         }
     }
 }
@@ -61,24 +82,936 @@ impl Display for ProcState {
     }
 }
 
+// Raw structure for deserializing conditions, threshold conditions take an optional `for` span
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ProcConditionRaw {
+    Seen {
+        #[serde(with = "humantime_serde")]
+        seen: Duration,
+    },
+    NotSeen {
+        #[serde(with = "humantime_serde")]
+        not_seen: Duration,
+    },
+    CountAbove {
+        count_above: usize,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+    },
+    CountBelow {
+        count_below: usize,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+    },
+    CpuAbove {
+        cpu_above: f32,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    CpuBelow {
+        cpu_below: f32,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    MemoryAbove {
+        memory_above: MemorySize,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+        #[serde(default)]
+        kind: MemoryKind,
+    },
+    MemoryBelow {
+        memory_below: MemorySize,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+        #[serde(default)]
+        kind: MemoryKind,
+    },
+    IoReadAbove {
+        io_read_above: ByteRate,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    IoWriteAbove {
+        io_write_above: ByteRate,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    CpuTimeAbove {
+        #[serde(with = "humantime_serde")]
+        cpu_time_above: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    AgeAbove {
+        #[serde(with = "humantime_serde")]
+        age_above: Duration,
+    },
+    Flapping {
+        flapping: FlappingRaw,
+    },
+    Status {
+        status: StatusRaw,
+    },
+    MemoryGrowth {
+        memory_growth: MemoryGrowthRaw,
+    },
+    FdsAbove {
+        fds_above: usize,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    ThreadsAbove {
+        threads_above: usize,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    ChildrenAbove {
+        children_above: usize,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+        #[serde(default)]
+        aggregate: Aggregate,
+    },
+    ConnectedTo {
+        connected_to: SocketPort,
+        #[serde(default, rename = "for", with = "humantime_serde")]
+        span: Duration,
+    },
+    Edge(EdgeRaw),
+}
+
+impl ProcConditionRaw {
+    /// Keys accepted by the variant, untagged variants ignore the keys they do not know
+    fn keys(&self) -> &'static [&'static str] {
+        match self {
+            ProcConditionRaw::Seen { .. } => &["seen"],
+            ProcConditionRaw::NotSeen { .. } => &["not_seen"],
+            ProcConditionRaw::CountAbove { .. } => &["count_above", "for"],
+            ProcConditionRaw::CountBelow { .. } => &["count_below", "for"],
+            ProcConditionRaw::CpuAbove { .. } => &["cpu_above", "for", "aggregate"],
+            ProcConditionRaw::CpuBelow { .. } => &["cpu_below", "for", "aggregate"],
+            ProcConditionRaw::MemoryAbove { .. } => &["memory_above", "for", "aggregate", "kind"],
+            ProcConditionRaw::MemoryBelow { .. } => &["memory_below", "for", "aggregate", "kind"],
+            ProcConditionRaw::IoReadAbove { .. } => &["io_read_above", "for", "aggregate"],
+            ProcConditionRaw::IoWriteAbove { .. } => &["io_write_above", "for", "aggregate"],
+            ProcConditionRaw::CpuTimeAbove { .. } => &["cpu_time_above", "aggregate"],
+            ProcConditionRaw::AgeAbove { .. } => &["age_above"],
+            ProcConditionRaw::Flapping { .. } => &["flapping"],
+            ProcConditionRaw::Status { .. } => &["status"],
+            ProcConditionRaw::MemoryGrowth { .. } => &["memory_growth"],
+            ProcConditionRaw::FdsAbove { .. } => &["fds_above", "for", "aggregate"],
+            ProcConditionRaw::ThreadsAbove { .. } => &["threads_above", "for", "aggregate"],
+            ProcConditionRaw::ChildrenAbove { .. } => &["children_above", "for", "aggregate"],
+            ProcConditionRaw::ConnectedTo { .. } => &["connected_to", "for"],
+            ProcConditionRaw::Edge(_) => &[],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct StatusRaw {
+    #[serde(rename = "in")]
+    statuses: Vec<ProcStatus>,
+    #[serde(default, rename = "for", with = "humantime_serde")]
+    span: Duration,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct MemoryGrowthRaw {
+    above: MemorySize,
+    #[serde(with = "humantime_serde")]
+    per: Duration,
+    #[serde(default)]
+    aggregate: Aggregate,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct FlappingRaw {
+    restarts: usize,
+    #[serde(with = "humantime_serde")]
+    within: Duration,
+}
+
+// Edge conditions are given as a plain string: `condition = "started"`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+enum EdgeRaw {
+    Started,
+    Exited,
+}
+
+impl TryFrom<toml::Value> for ProcCondition {
+    type Error = de::value::Error;
+
+    fn try_from(value: toml::Value) -> Result<Self, Self::Error> {
+        // the untagged enum only reports that no variant matched, point at the condition instead
+        let raw = ProcConditionRaw::deserialize(value.clone())
+            .map_err(|_| de::Error::custom(format!("invalid condition `{value}`")))?;
+
+        if let toml::Value::Table(table) = &value {
+            let keys = raw.keys();
+            if let Some(key) = table.keys().find(|key| !keys.contains(&key.as_str())) {
+                return Err(de::Error::custom(format!(
+                    "unknown key `{key}` in condition `{value}`, expected one of: {}", keys.join(", ")
+                )));
+            }
+        }
+        Ok(raw.into())
+    }
+}
+
+impl From<ProcConditionRaw> for ProcCondition {
+    fn from(raw: ProcConditionRaw) -> Self {
+        match raw {
+            ProcConditionRaw::Seen { seen } => ProcCondition::Seen(seen),
+            ProcConditionRaw::NotSeen { not_seen } => ProcCondition::NotSeen(not_seen),
+            ProcConditionRaw::CountAbove { count_above, span } => {
+                ProcCondition::CountAbove { count: count_above, span }
+            }
+            ProcConditionRaw::CountBelow { count_below, span } => {
+                ProcCondition::CountBelow { count: count_below, span }
+            }
+            ProcConditionRaw::CpuAbove { cpu_above, span, aggregate } => {
+                ProcCondition::CpuAbove { percent: cpu_above, span, aggregate }
+            }
+            ProcConditionRaw::CpuBelow { cpu_below, span, aggregate } => {
+                ProcCondition::CpuBelow { percent: cpu_below, span, aggregate }
+            }
+            ProcConditionRaw::MemoryAbove { memory_above, span, aggregate, kind } => {
+                ProcCondition::MemoryAbove { bytes: memory_above.0, span, aggregate, kind }
+            }
+            ProcConditionRaw::MemoryBelow { memory_below, span, aggregate, kind } => {
+                ProcCondition::MemoryBelow { bytes: memory_below.0, span, aggregate, kind }
+            }
+            ProcConditionRaw::IoReadAbove { io_read_above, span, aggregate } => {
+                ProcCondition::IoReadAbove { rate: io_read_above.0, span, aggregate }
+            }
+            ProcConditionRaw::IoWriteAbove { io_write_above, span, aggregate } => {
+                ProcCondition::IoWriteAbove { rate: io_write_above.0, span, aggregate }
+            }
+            ProcConditionRaw::CpuTimeAbove { cpu_time_above, aggregate } => {
+                ProcCondition::CpuTimeAbove { time: cpu_time_above, aggregate }
+            }
+            ProcConditionRaw::AgeAbove { age_above } => ProcCondition::AgeAbove(age_above),
+            ProcConditionRaw::Flapping { flapping } => {
+                ProcCondition::Flapping { restarts: flapping.restarts, within: flapping.within }
+            }
+            ProcConditionRaw::Status { status } => {
+                ProcCondition::Status { statuses: status.statuses, span: status.span }
+            }
+            ProcConditionRaw::MemoryGrowth { memory_growth } => ProcCondition::MemoryGrowth {
+                bytes: memory_growth.above.0,
+                per: memory_growth.per,
+                aggregate: memory_growth.aggregate,
+            },
+            ProcConditionRaw::FdsAbove { fds_above, span, aggregate } => {
+                ProcCondition::FdsAbove { count: fds_above, span, aggregate }
+            }
+            ProcConditionRaw::ThreadsAbove { threads_above, span, aggregate } => {
+                ProcCondition::ThreadsAbove { count: threads_above, span, aggregate }
+            }
+            ProcConditionRaw::ChildrenAbove { children_above, span, aggregate } => {
+                ProcCondition::ChildrenAbove { count: children_above, span, aggregate }
+            }
+            ProcConditionRaw::ConnectedTo { connected_to, span } => {
+                ProcCondition::ConnectedTo { port: connected_to, span }
+            }
+            ProcConditionRaw::Edge(EdgeRaw::Started) => ProcCondition::Started,
+            ProcConditionRaw::Edge(EdgeRaw::Exited) => ProcCondition::Exited,
+        }
+    }
+}
+
 /// User defined condition on a Process
 #[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "toml::Value")]
 pub enum ProcCondition {
-    #[serde(rename = "seen", with = "humantime_serde")]
     Seen(Duration),
 
-    #[serde(rename = "not_seen", with = "humantime_serde")]
     NotSeen(Duration),
-    //TODO: resource management: ram, cpu, IO ...
+
+    /// more than `count` processes matched during `span`
+    CountAbove { count: usize, span: Duration },
+
+    /// less than `count` processes matched during `span`
+    CountBelow { count: usize, span: Duration },
+
+    /// cpu usage of the matched processes above `percent` during `span`, a process using
+    /// several cores can go over 100%
+    CpuAbove { percent: f32, span: Duration, aggregate: Aggregate },
+
+    /// cpu usage of the matched processes below `percent` during `span`, only while
+    /// some processes are matched
+    CpuBelow { percent: f32, span: Duration, aggregate: Aggregate },
+
+    /// memory used by the matched processes above `bytes` during `span`
+    MemoryAbove { bytes: u64, span: Duration, aggregate: Aggregate, kind: MemoryKind },
+
+    /// memory used by the matched processes below `bytes` during `span`, only while
+    /// some processes are matched
+    MemoryBelow { bytes: u64, span: Duration, aggregate: Aggregate, kind: MemoryKind },
+
+    /// disk read throughput of the matched processes above `rate` bytes per second during `span`
+    IoReadAbove { rate: u64, span: Duration, aggregate: Aggregate },
+
+    /// disk write throughput of the matched processes above `rate` bytes per second during `span`
+    IoWriteAbove { rate: u64, span: Duration, aggregate: Aggregate },
+
+    /// cpu time (user + system) used by the matched processes since they started above `time`
+    CpuTimeAbove { time: Duration, aggregate: Aggregate },
+
+    /// the oldest matched process was started more than the given duration ago.
+    /// Unlike `Seen` it does not depend on when pswatch first saw the process.
+    AgeAbove(Duration),
+
+    /// a process joined the matched processes since the last refresh, fires once per process
+    Started,
+
+    /// a process left the matched processes since the last refresh, fires once per process
+    Exited,
+
+    /// the process restarted at least `restarts` times during the last `within`. Reappearing
+    /// after being not seen and a pid replaced by a new one both count as a restart.
+    Flapping { restarts: usize, within: Duration },
+
+    /// some matched process has been in one of `statuses` for `span`, the time is tracked
+    /// for each process on its own
+    Status { statuses: Vec<ProcStatus>, span: Duration },
+
+    /// resident memory of the matched processes grew by more than `bytes` per `per`,
+    /// measured by the trend of the samples taken during the last `per`
+    MemoryGrowth { bytes: u64, per: Duration, aggregate: Aggregate },
+
+    /// file descriptors opened by the matched processes above `count` during `span`
+    FdsAbove { count: usize, span: Duration, aggregate: Aggregate },
+
+    /// threads of the matched processes above `count` during `span`
+    ThreadsAbove { count: usize, span: Duration, aggregate: Aggregate },
+
+    /// direct children of the matched processes above `count` during `span`
+    ChildrenAbove { count: usize, span: Duration, aggregate: Aggregate },
+
+    /// some matched process held a connection to the remote `port` during `span`
+    ConnectedTo { port: SocketPort, span: Duration },
+}
+
+/// Scheduler state of a process
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcStatus {
+    Idle,
+    Running,
+    Sleeping,
+
+    /// uninterruptible sleep, usually waiting on I/O
+    DiskSleep,
+    Stopped,
+    Tracing,
+    Zombie,
+    Dead,
+    Wakekill,
+    Waking,
+    Parked,
+    LockBlocked,
+    Unknown,
+}
+
+impl From<ProcessStatus> for ProcStatus {
+    fn from(status: ProcessStatus) -> Self {
+        match status {
+            ProcessStatus::Idle => ProcStatus::Idle,
+            ProcessStatus::Run => ProcStatus::Running,
+            ProcessStatus::Sleep => ProcStatus::Sleeping,
+            ProcessStatus::UninterruptibleDiskSleep => ProcStatus::DiskSleep,
+            ProcessStatus::Stop => ProcStatus::Stopped,
+            ProcessStatus::Tracing => ProcStatus::Tracing,
+            ProcessStatus::Zombie => ProcStatus::Zombie,
+            ProcessStatus::Dead => ProcStatus::Dead,
+            ProcessStatus::Wakekill => ProcStatus::Wakekill,
+            ProcessStatus::Waking => ProcStatus::Waking,
+            ProcessStatus::Parked => ProcStatus::Parked,
+            ProcessStatus::LockBlocked => ProcStatus::LockBlocked,
+            ProcessStatus::Unknown(_) => ProcStatus::Unknown,
+        }
+    }
+}
+
+/// Processes in these states are ignored unless the profile sets its own `exclude_status`
+pub fn default_exclude_status() -> Vec<ProcStatus> {
+    vec![ProcStatus::Stopped, ProcStatus::Dead, ProcStatus::Zombie]
+}
+
+/// Memory measured by the memory conditions
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryKind {
+    /// resident set size, the memory mapped in RAM
+    #[default]
+    Rss,
+
+    /// virtual memory size
+    Virtual,
+}
+
+/// A memory size given in bytes, as a human readable size ("512MiB", "2G") or as a
+/// percentage of the total RAM ("25%")
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "SizeRaw")]
+pub struct MemorySize(pub u64);
+
+/// A disk throughput in bytes per second, given in bytes or as a human readable size with
+/// an optional `/s` suffix ("50MiB/s")
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "SizeRaw")]
+pub struct ByteRate(pub u64);
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum SizeRaw {
+    Bytes(u64),
+    Size(String),
+}
+
+impl TryFrom<SizeRaw> for MemorySize {
+    type Error = String;
+
+    fn try_from(raw: SizeRaw) -> Result<Self, Self::Error> {
+        match raw {
+            SizeRaw::Bytes(bytes) => Ok(MemorySize(bytes)),
+            SizeRaw::Size(size) => parse_size(&size, total_memory()).map(MemorySize),
+        }
+    }
+}
+
+impl TryFrom<SizeRaw> for ByteRate {
+    type Error = String;
+
+    fn try_from(raw: SizeRaw) -> Result<Self, Self::Error> {
+        match raw {
+            SizeRaw::Bytes(bytes) => Ok(ByteRate(bytes)),
+            SizeRaw::Size(size) if size.contains('%') => Err(format!("invalid disk throughput: {size}")),
+            SizeRaw::Size(size) => {
+                let size = size.trim();
+                parse_size(size.strip_suffix("/s").unwrap_or(size), 0).map(ByteRate)
+            }
+        }
+    }
+}
+
+/// Parses a memory size, `K`, `M`, `G` and `T` units are powers of 1024 while `KB`, `MB` ...
+/// are powers of 1000. A percentage is relative to `total`.
+fn parse_size(size: &str, total: u64) -> Result<u64, String> {
+    let size = size.trim();
+    let invalid = || format!("invalid memory size: {size}");
+
+    if let Some(percent) = size.strip_suffix('%') {
+        let percent: f64 = percent.trim().parse().map_err(|_| invalid())?;
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(format!("memory percentage out of range: {size}"));
+        }
+        return Ok((total as f64 * percent / 100.0) as u64);
+    }
+
+    let unit_start = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (value, unit) = size.split_at(unit_start);
+    let value: f64 = value.parse().map_err(|_| invalid())?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(invalid()),
+    };
+    Ok((value * multiplier as f64) as u64)
+}
+
+/// Total RAM of the system in bytes
+fn total_memory() -> u64 {
+    static TOTAL_MEMORY: OnceLock<u64> = OnceLock::new();
+    *TOTAL_MEMORY.get_or_init(|| {
+        let specs = RefreshKind::new().with_memory(MemoryRefreshKind::new().with_ram());
+        sysinfo::System::new_with_specifics(specs).total_memory()
+    })
+}
+
+/// How the usage of the matched processes is combined
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+    /// total usage of all the processes
+    #[default]
+    Sum,
+
+    /// usage of the process using the most
+    Max,
 }
 
 impl ProcCondition {
-    fn span(&self) -> Duration {
+    pub fn span(&self) -> Duration {
         match self {
             ProcCondition::Seen(s) => *s,
             ProcCondition::NotSeen(s) => *s,
+            ProcCondition::CountAbove { span, .. } => *span,
+            ProcCondition::CountBelow { span, .. } => *span,
+            ProcCondition::CpuAbove { span, .. } => *span,
+            ProcCondition::CpuBelow { span, .. } => *span,
+            ProcCondition::MemoryAbove { span, .. } => *span,
+            ProcCondition::MemoryBelow { span, .. } => *span,
+            ProcCondition::IoReadAbove { span, .. } => *span,
+            ProcCondition::IoWriteAbove { span, .. } => *span,
+            ProcCondition::Flapping { within, .. } => *within,
+            ProcCondition::Status { span, .. } => *span,
+            ProcCondition::MemoryGrowth { per, .. } => *per,
+            ProcCondition::FdsAbove { span, .. } => *span,
+            ProcCondition::ThreadsAbove { span, .. } => *span,
+            ProcCondition::ChildrenAbove { span, .. } => *span,
+            ProcCondition::ConnectedTo { span, .. } => *span,
+            ProcCondition::CpuTimeAbove { .. }
+            | ProcCondition::AgeAbove(_)
+            | ProcCondition::Started
+            | ProcCondition::Exited => Duration::ZERO,
         }
     }
+
+    /// Edge conditions fire once for each process joining or leaving the matched processes,
+    /// they have no end state
+    pub fn is_edge(&self) -> bool {
+        matches!(self, ProcCondition::Started | ProcCondition::Exited)
+    }
+
+    /// Extends `kind` with the process data needed to evaluate this condition.
+    /// Used to only refresh costly data when some command needs it.
+    pub fn refresh_kind(&self, kind: ProcessRefreshKind) -> ProcessRefreshKind {
+        match self {
+            ProcCondition::CpuAbove { .. } | ProcCondition::CpuBelow { .. } => kind.with_cpu(),
+            ProcCondition::MemoryAbove { .. }
+            | ProcCondition::MemoryBelow { .. }
+            | ProcCondition::MemoryGrowth { .. } => kind.with_memory(),
+            ProcCondition::IoReadAbove { .. } | ProcCondition::IoWriteAbove { .. } => kind.with_disk_usage(),
+            ProcCondition::Seen(_)
+            | ProcCondition::NotSeen(_)
+            | ProcCondition::CountAbove { .. }
+            | ProcCondition::CountBelow { .. }
+            | ProcCondition::CpuTimeAbove { .. }
+            | ProcCondition::AgeAbove(_)
+            | ProcCondition::Started
+            | ProcCondition::Exited
+            | ProcCondition::Flapping { .. }
+            | ProcCondition::Status { .. }
+            | ProcCondition::FdsAbove { .. }
+            | ProcCondition::ThreadsAbove { .. }
+            | ProcCondition::ChildrenAbove { .. }
+            | ProcCondition::ConnectedTo { .. } => kind,
+        }
+    }
+
+    /// Extends `metrics` with the metrics read from `/proc` needed to evaluate this condition
+    pub fn procfs_metrics(&self, metrics: ProcfsMetrics) -> ProcfsMetrics {
+        match self {
+            ProcCondition::CpuTimeAbove { .. } => metrics.with_cpu_time(),
+            ProcCondition::FdsAbove { .. } => metrics.with_fds(),
+            ProcCondition::ThreadsAbove { .. } => metrics.with_threads(),
+            ProcCondition::ChildrenAbove { .. } => metrics.with_children(),
+            ProcCondition::ConnectedTo { .. } => metrics.with_connections(),
+            _ => metrics,
+        }
+    }
+}
+
+/// Metrics that sysinfo does not provide, read from `/proc` for each matched process.
+/// The children count is derived from the process table instead.
+/// They are only collected when some condition uses them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProcfsMetrics {
+    pub cpu_time: bool,
+    pub fds: bool,
+    pub threads: bool,
+    pub children: bool,
+    pub connections: bool,
+}
+
+impl ProcfsMetrics {
+    pub fn with_cpu_time(mut self) -> Self {
+        self.cpu_time = true;
+        self
+    }
+
+    pub fn with_fds(mut self) -> Self {
+        self.fds = true;
+        self
+    }
+
+    pub fn with_threads(mut self) -> Self {
+        self.threads = true;
+        self
+    }
+
+    pub fn with_children(mut self) -> Self {
+        self.children = true;
+        self
+    }
+
+    pub fn with_connections(mut self) -> Self {
+        self.connections = true;
+        self
+    }
+}
+
+/// Metrics of the matched processes taken on a refresh
+#[derive(Debug, Clone)]
+struct Sample {
+    time: Instant,
+
+    /// number of matched processes
+    count: usize,
+
+    /// cpu usage in percent
+    cpu: Usage<f32>,
+
+    /// resident set size in bytes
+    memory: Usage<u64>,
+
+    /// resident set size of each process
+    rss: HashMap<usize, u64>,
+
+    /// virtual memory size in bytes
+    virtual_memory: Usage<u64>,
+
+    /// disk read throughput in bytes per second
+    io_read: Usage<u64>,
+
+    /// disk write throughput in bytes per second
+    io_write: Usage<u64>,
+
+    /// bytes read and written by each process since it started
+    io_totals: HashMap<usize, IoTotals>,
+
+    /// cpu time used since the processes started
+    cpu_time: Usage<Duration>,
+
+    /// age of the oldest process
+    age: Duration,
+
+    /// status of each process
+    statuses: HashMap<usize, StatusSince>,
+
+    /// opened file descriptors
+    fds: Usage<usize>,
+
+    /// processes whose file descriptors could not be counted for lack of privileges
+    fds_denied: usize,
+
+    /// threads, the main threads included
+    threads: Usage<usize>,
+
+    /// direct children
+    children: Usage<usize>,
+
+    /// remote ports the processes are connected to
+    connections: HashSet<SocketPort>,
+
+    /// why some connections could not be read
+    connections_error: Option<String>,
+}
+
+/// Status of a process and when it entered it, as seen by the refreshes
+#[derive(Debug, Clone, Copy)]
+struct StatusSince {
+    status: ProcStatus,
+    since: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IoTotals {
+    read: u64,
+    written: u64,
+}
+
+impl Default for Sample {
+    /// Sample taken now without any matched process
+    fn default() -> Self {
+        Self {
+            time: Instant::now(),
+            count: 0,
+            cpu: Usage::default(),
+            memory: Usage::default(),
+            rss: HashMap::new(),
+            virtual_memory: Usage::default(),
+            io_read: Usage::default(),
+            io_write: Usage::default(),
+            io_totals: HashMap::new(),
+            cpu_time: Usage::default(),
+            age: Duration::ZERO,
+            statuses: HashMap::new(),
+            fds: Usage::default(),
+            fds_denied: 0,
+            threads: Usage::default(),
+            children: Usage::default(),
+            connections: HashSet::new(),
+            connections_error: None,
+        }
+    }
+}
+
+impl Sample {
+    /// Collects the metrics of `pids`, `prev` is the previous sample used to compute rates
+    fn collect(
+        pids: &[usize],
+        info: &sysinfo::System,
+        time: Instant,
+        prev: Option<&Sample>,
+        procfs_metrics: ProcfsMetrics,
+    ) -> Self {
+        let procs = || pids.iter().filter_map(|pid| info.process(Pid::from(*pid)));
+        let fds: Vec<_> = if procfs_metrics.fds {
+            procs().map(|p| procfs::fd_count(p.pid())).collect()
+        } else {
+            vec![]
+        };
+        let (connections, connections_error) = if procfs_metrics.connections {
+            remote_ports(procs())
+        } else {
+            (HashSet::new(), None)
+        };
+        let mut sample = Self {
+            time,
+            count: pids.len(),
+            cpu: Usage::of(procs().map(|p| p.cpu_usage())),
+            memory: Usage::of(procs().map(|p| p.memory())),
+            rss: procs().map(|p| (p.pid().into(), p.memory())).collect(),
+            virtual_memory: Usage::of(procs().map(|p| p.virtual_memory())),
+            io_read: Usage::default(),
+            io_write: Usage::default(),
+            io_totals: procs()
+                .map(|p| {
+                    let usage = p.disk_usage();
+                    let totals = IoTotals { read: usage.total_read_bytes, written: usage.total_written_bytes };
+                    (p.pid().into(), totals)
+                })
+                .collect(),
+            cpu_time: if procfs_metrics.cpu_time {
+                Usage::of(procs().filter_map(|p| procfs::cpu_time(p.pid()).ok()))
+            } else {
+                Usage::default()
+            },
+            age: Duration::from_secs(procs().map(|p| p.run_time()).max().unwrap_or_default()),
+            statuses: procs()
+                .map(|p| (p.pid().into(), StatusSince { status: p.status().into(), since: time }))
+                .collect(),
+            fds: Usage::of(fds.iter().filter_map(|count| count.as_ref().ok().copied())),
+            fds_denied: fds
+                .iter()
+                .filter(|count| count.as_ref().is_err_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied))
+                .count(),
+            threads: if procfs_metrics.threads {
+                Usage::of(procs().filter_map(|p| procfs::thread_count(p.pid()).ok()))
+            } else {
+                Usage::default()
+            },
+            children: if procfs_metrics.children {
+                let children = children_map(info);
+                Usage::of(procs().map(|p| children.get(&p.pid()).map_or(0, Vec::len)))
+            } else {
+                Usage::default()
+            },
+            connections,
+            connections_error,
+        };
+        if let Some(prev) = prev {
+            sample.update_io_rates(prev);
+            sample.update_statuses(prev);
+        }
+        sample
+    }
+
+    /// Computes the disk throughput of the processes that were already matched by `prev`
+    fn update_io_rates(&mut self, prev: &Sample) {
+        let secs = self.time.duration_since(prev.time).as_secs_f64();
+        if secs <= 0.0 {
+            return;
+        }
+
+        let rates = |bytes: fn(&IoTotals) -> u64| {
+            Usage::of(self.io_totals.iter().filter_map(|(pid, totals)| {
+                let prev = prev.io_totals.get(pid)?;
+                Some((bytes(totals).saturating_sub(bytes(prev)) as f64 / secs) as u64)
+            }))
+        };
+        self.io_read = rates(|t| t.read);
+        self.io_write = rates(|t| t.written);
+    }
+
+    /// Carries over when the processes entered their status if they were already in it in `prev`
+    fn update_statuses(&mut self, prev: &Sample) {
+        for (pid, current) in self.statuses.iter_mut() {
+            if let Some(prev) = prev.statuses.get(pid).filter(|prev| prev.status == current.status) {
+                current.since = prev.since;
+            }
+        }
+    }
+
+    /// Whether some process has been in any of `statuses` for `span`
+    fn in_status(&self, statuses: &[ProcStatus], span: Duration) -> bool {
+        self.statuses
+            .values()
+            .any(|s| statuses.contains(&s.status) && s.since.elapsed() >= span)
+    }
+
+    fn memory(&self, kind: MemoryKind, aggregate: Aggregate) -> u64 {
+        match kind {
+            MemoryKind::Rss => self.memory.get(aggregate),
+            MemoryKind::Virtual => self.virtual_memory.get(aggregate),
+        }
+    }
+}
+
+/// Usage of a resource by the matched processes
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage<T> {
+    total: T,
+    max: T,
+}
+
+impl<T> Usage<T>
+where
+    T: Copy + Default + PartialOrd + Add<Output = T>,
+{
+    fn of(values: impl Iterator<Item = T>) -> Self {
+        values.fold(Self::default(), |usage, v| Self {
+            total: usage.total + v,
+            max: if v > usage.max { v } else { usage.max },
+        })
+    }
+
+    fn get(&self, aggregate: Aggregate) -> T {
+        match aggregate {
+            Aggregate::Sum => self.total,
+            Aggregate::Max => self.max,
+        }
+    }
+}
+
+/// Samples of the last refreshes, kept for `retention` to evaluate conditions that have
+/// to hold for some time
+#[derive(Debug, Clone, Default)]
+struct History {
+    samples: VecDeque<Sample>,
+    retention: Duration,
+}
+
+impl History {
+    fn push(&mut self, sample: Sample) {
+        self.samples.push_back(sample);
+
+        // keep the newest sample older than the retention, it covers the start of the span
+        while self.samples.get(1).is_some_and(|s| s.time.elapsed() >= self.retention) {
+            self.samples.pop_front();
+        }
+    }
+
+    fn latest(&self) -> Option<&Sample> {
+        self.samples.back()
+    }
+
+    /// Whether `pred` held on every sample taken during the last `span`
+    fn sustained(&self, span: Duration, pred: impl Fn(&Sample) -> bool) -> bool {
+        for sample in self.samples.iter().rev() {
+            if !pred(sample) {
+                return false;
+            }
+            if sample.time.elapsed() >= span {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Growth of the resident memory per second over the samples taken during the last `span`.
+    /// Only the processes matched by all these samples count, processes joining or leaving
+    /// the group are not growth. None until the samples cover the span, or if the memory
+    /// does not follow a steady trend.
+    fn memory_growth(&self, span: Duration, aggregate: Aggregate) -> Option<f64> {
+        let latest = self.latest()?;
+        if self.samples.front()?.time.elapsed() < span {
+            return None;
+        }
+        let mut window = vec![];
+        for sample in self.samples.iter().rev() {
+            window.push(sample);
+            if sample.time.elapsed() >= span {
+                break;
+            }
+        }
+        let stable: Vec<usize> = latest
+            .rss
+            .keys()
+            .filter(|pid| window.iter().all(|s| s.rss.contains_key(pid)))
+            .copied()
+            .collect();
+        let age = |s: &Sample| latest.time.duration_since(s.time).as_secs_f64();
+
+        match aggregate {
+            Aggregate::Sum if stable.is_empty() => None,
+            Aggregate::Sum => {
+                trend(window.iter().map(|s| (age(s), stable.iter().map(|pid| s.rss[pid] as f64).sum())))
+            }
+            Aggregate::Max => stable
+                .iter()
+                .filter_map(|pid| trend(window.iter().map(|s| (age(s), s.rss[pid] as f64))))
+                .max_by(f64::total_cmp),
+        }
+    }
+}
+
+/// Slope per second of the `(age, value)` points, computed by least squares. None with less
+/// than 3 points or if they are too far from a line, a single step is not a trend.
+fn trend(points: impl Iterator<Item = (f64, f64)>) -> Option<f64> {
+    const MIN_FIT: f64 = 0.9;
+
+    let points: Vec<(f64, f64)> = points.collect();
+    if points.len() < 3 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let (mean_x, mean_y) = points.iter().fold((0.0, 0.0), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+    let (cov, var_x, var_y) = points.iter().fold((0.0, 0.0, 0.0), |(cov, var_x, var_y), (x, y)| {
+        (cov + (x - mean_x) * (y - mean_y), var_x + (x - mean_x).powi(2), var_y + (y - mean_y).powi(2))
+    });
+    if var_x <= 0.0 || var_y <= 0.0 {
+        return None;
+    }
+    // coefficient of determination of the fitted line
+    let fit = cov * cov / (var_x * var_y);
+    // x is the age of the samples, growth over time has a negative slope against it
+    (fit >= MIN_FIT).then(|| -cov / var_x)
 }
 
 #[derive(Debug)]
@@ -86,22 +1019,248 @@ pub struct Process {
     matcher: ProcessMatcher,
     lifetime: ProcLifetime,
     pids: Vec<usize>,
+
+    /// processes matching any of these are ignored
+    exclude: Vec<ProcessMatcher>,
+
+    /// ignore pswatch itself and the commands it spawned
+    exclude_self: bool,
+
+    history: History,
+
+    procfs_metrics: ProcfsMetrics,
+
+    /// also track the descendants of the matched processes
+    include_descendants: bool,
+
+    /// processes in any of these states are ignored
+    exclude_status: Vec<ProcStatus>,
+
+    /// pids that joined the matched processes on the last refresh
+    started: Vec<usize>,
+
+    /// pids that left the matched processes on the last refresh
+    exited: Vec<usize>,
+
+    /// report the processes matched on the first refresh as started, they are
+    /// considered already running otherwise
+    report_running: bool,
+
+    /// pids matched on the last refresh, their descendants excluded
+    roots: HashSet<usize>,
+
+    /// matched pids replaced by new ones on the last refresh, their descendants excluded
+    replaced: usize,
+
+    /// the processes whose file descriptors cannot be counted were reported
+    fds_denied_reported: bool,
+
+    /// the connections that cannot be read were reported
+    connections_error_reported: bool,
 }
 
-impl Process {
-    pub fn build(matcher: ProcessMatcher, state_matcher: ProcLifetime) -> Self {
-        Self {
-            matcher,
-            lifetime: state_matcher,
-            pids: vec![],
+impl Process {
+    pub fn build(matcher: ProcessMatcher, state_matcher: ProcLifetime) -> Self {
+        Self {
+            matcher,
+            lifetime: state_matcher,
+            pids: vec![],
+            exclude: vec![],
+            exclude_self: false,
+            history: History::default(),
+            procfs_metrics: ProcfsMetrics::default(),
+            include_descendants: false,
+            exclude_status: default_exclude_status(),
+            started: vec![],
+            exited: vec![],
+            report_running: false,
+            roots: HashSet::new(),
+            replaced: 0,
+            fds_denied_reported: false,
+            connections_error_reported: false,
+        }
+    }
+
+    pub fn from_pattern(pat: impl Into<ProcessMatcher>) -> Self {
+        Self::build(pat.into(), ProcLifetime::new())
+    }
+
+    pub fn with_exclude(mut self, exclude: Vec<ProcessMatcher>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    pub fn with_exclude_self(mut self, exclude_self: bool) -> Self {
+        self.exclude_self = exclude_self;
+        self
+    }
+
+    /// Keeps the refresh samples for `retention`, the longest span of the conditions
+    /// evaluated on this process
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.history.retention = retention;
+        self
+    }
+
+    pub fn with_procfs_metrics(mut self, procfs_metrics: ProcfsMetrics) -> Self {
+        self.procfs_metrics = procfs_metrics;
+        self
+    }
+
+    pub fn with_descendants(mut self, include_descendants: bool) -> Self {
+        self.include_descendants = include_descendants;
+        self
+    }
+
+    pub fn with_exclude_status(mut self, exclude_status: Vec<ProcStatus>) -> Self {
+        self.exclude_status = exclude_status;
+        self
+    }
+
+    pub fn with_report_running(mut self, report_running: bool) -> Self {
+        self.report_running = report_running;
+        self
+    }
+
+    /// Extends `kind` with the process data needed to match this process
+    pub fn refresh_kind(&self, kind: ProcessRefreshKind) -> ProcessRefreshKind {
+        self.exclude
+            .iter()
+            .fold(self.matcher.refresh_kind(kind), |kind, m| m.refresh_kind(kind))
+    }
+
+    pub fn pids(&self) -> &[usize] {
+        &self.pids
+    }
+
+    /// Pids an edge condition fired for on the last refresh, `None` for other conditions
+    pub fn edge_pids(&self, c: &ProcCondition) -> Option<&[usize]> {
+        match c {
+            ProcCondition::Started => Some(&self.started),
+            ProcCondition::Exited => Some(&self.exited),
+            _ => None,
+        }
+    }
+
+    /// Time elapsed since the process was last seen, if it is not seen anymore
+    pub fn unseen_for(&self) -> Option<Duration> {
+        match self.lifetime.state {
+            ProcState::NotSeen => self.lifetime.last_seen.map(|t| t.elapsed()),
+            _ => None,
+        }
+    }
+
+    /// Returns the pids of the running processes matching this process, descendants excluded
+    pub fn match_pids(&mut self, info: &sysinfo::System) -> Vec<usize> {
+        self.matcher.refresh();
+        self.exclude.iter_mut().for_each(ProcessMatcher::refresh);
+
+        info.processes()
+            .iter()
+            // threads would be matched and counted along their process
+            .filter(|(_, proc)| !is_thread(proc))
+            .filter(|(_, proc)| proc.match_by(&self.matcher, info))
+            .filter(|(_, proc)| !self.is_excluded(proc, info))
+
+            // filter out processes in an excluded state, stopped and dead ones by default
+            .filter(|(_, proc)| !self.is_excluded_status(proc))
+            .map(|(_, proc)| proc.pid().into())
+            .collect()
+    }
+
+    /// Updates the state with `pids` as the matched processes at `t_refresh`
+    pub fn track(&mut self, pids: Vec<usize>, info: &sysinfo::System, t_refresh: Instant) -> ProcState {
+        let prev_pids: HashSet<usize> = std::mem::replace(&mut self.pids, pids).into_iter().collect();
+
+        // only the matched pids restart, the descendants come and go with their work
+        let prev_roots = std::mem::replace(&mut self.roots, self.pids.iter().copied().collect());
+        self.replaced = self
+            .roots
+            .difference(&prev_roots)
+            .count()
+            .min(prev_roots.difference(&self.roots).count());
+
+        if self.include_descendants {
+            self.add_descendants(info);
+        }
+
+        // the first refresh only seeds the pids, a restarted pswatch does not report
+        // the processes that were already running as started
+        let first_refresh = self.lifetime.last_refresh.is_none();
+        let pids: HashSet<usize> = self.pids.iter().copied().collect();
+        self.started = if first_refresh && !self.report_running {
+            vec![]
+        } else {
+            self.pids.iter().filter(|pid| !prev_pids.contains(pid)).copied().collect()
+        };
+        self.exited = prev_pids.difference(&pids).copied().collect();
+
+        debug!("<{}> detected pids: {}", self.matcher, self.pids.len());
+        // trace matched pids
+
+        if log_enabled!(log::Level::Trace) {
+            self.pids
+                .iter()
+                .filter_map(|pid| info.processes().get(&Pid::from(*pid)))
+                .for_each(|p| trace!("- {}: \n {:#?}", p.pid(), p));
+        }
+
+        self.lifetime.prev_refresh = self.lifetime.last_refresh;
+        self.lifetime.last_refresh = Some(t_refresh);
+
+        self.record_sample(info, t_refresh);
+        self.update_inner_state();
+        self.lifetime.state.clone()
+    }
+
+    fn is_excluded(&self, proc: &sysinfo::Process, info: &sysinfo::System) -> bool {
+        (self.exclude_self && is_self(proc)) || self.exclude.iter().any(|m| proc.match_by(m, info))
+    }
+
+    fn is_excluded_status(&self, proc: &sysinfo::Process) -> bool {
+        self.exclude_status.contains(&proc.status().into())
+    }
+
+    /// Adds the descendants of the matched processes to the tracked pids
+    fn add_descendants(&mut self, info: &sysinfo::System) {
+        let children = children_map(info);
+        let mut visited: HashSet<usize> = self.pids.iter().copied().collect();
+        let mut queue = self.pids.clone();
+        while let Some(pid) = queue.pop() {
+            for child in children.get(&Pid::from(pid)).into_iter().flatten() {
+                let child_pid: usize = child.pid().into();
+                // the subtree of an excluded process is excluded as well
+                if self.is_excluded(child, info) || !visited.insert(child_pid) {
+                    continue;
+                }
+                if !self.is_excluded_status(child) {
+                    self.pids.push(child_pid);
+                }
+                queue.push(child_pid);
+            }
+        }
+    }
+
+    fn record_sample(&mut self, info: &sysinfo::System, t_refresh: Instant) {
+        let sample = Sample::collect(&self.pids, info, t_refresh, self.history.latest(), self.procfs_metrics);
+        if sample.fds_denied > 0 && !self.fds_denied_reported {
+            warn!(
+                "<{}>: cannot count the file descriptors of {} processes owned by another user, \
+                run pswatch as root or with CAP_SYS_PTRACE to count them",
+                self.matcher, sample.fds_denied
+            );
+            self.fds_denied_reported = true;
         }
+        if let Some(e) = sample.connections_error.as_ref().filter(|_| !self.connections_error_reported) {
+            warn!("<{}>: cannot read the connections of the processes: {}", self.matcher, e);
+            self.connections_error_reported = true;
+        }
+        self.history.push(sample);
     }
 
-    pub fn from_pattern(pat: impl Into<ProcessMatcher>) -> Self {
-        Self {
-            matcher: pat.into(),
-            lifetime: ProcLifetime::new(),
-            pids: vec![],
+    fn record_restarts(&mut self, count: usize) {
+        if let Some(t_refresh) = self.lifetime.last_refresh {
+            self.lifetime.record_restarts(count, t_refresh, self.history.retention);
         }
     }
 
@@ -128,6 +1287,7 @@ impl Process {
                 }
                 ProcState::NotSeen => {
                     debug!("<{}>: process reappeared", self.matcher);
+                    self.record_restarts(1);
                     self.lifetime.state_exit = true;
 
                     // reset first_seen
@@ -136,6 +1296,8 @@ impl Process {
                 ProcState::Seen => {
                     self.lifetime.state_exit = false;
                     debug!("<{}>: process still running", self.matcher);
+                    // pids replaced while the process stays seen
+                    self.record_restarts(self.replaced);
                 }
             }
             self.lifetime.prev_state = Some(self.lifetime.state.clone());
@@ -145,40 +1307,75 @@ impl Process {
     }
 }
 
-impl StateTracker for Process {
-    type State = ProcState;
+/// Remote ports of the connected sockets held by `procs`, with the last error of the socket
+/// tables or file descriptors that could not be read
+fn remote_ports<'a>(procs: impl Iterator<Item = &'a sysinfo::Process>) -> (HashSet<SocketPort>, Option<String>) {
+    let mut error = None;
+    let mut peers = HashMap::new();
+    for proto in [Proto::Tcp, Proto::Udp] {
+        match procfs::sockets(proto) {
+            Ok(sockets) => peers.extend(
+                sockets
+                    .iter()
+                    .filter(|s| s.is_connected())
+                    .map(|s| (s.inode, SocketPort { proto: s.proto, port: s.remote_port })),
+            ),
+            Err(e) => error = Some(format!("{} socket tables: {}", proto, e)),
+        }
+    }
+    if peers.is_empty() {
+        return (HashSet::new(), error);
+    }
 
-    /// updates the state and return a copy of the new state
-    fn update_state(&mut self, info: &sysinfo::System, t_refresh: Instant) -> ProcState {
-        self.pids = info
-            .processes()
-            .iter()
-            // .filter(|(_, proc)| MatchBy::match_by(*proc, self.matching.pattern.clone()))
-            .filter(|(_, proc)| proc.match_by(self.matcher.clone()))
-            // .inspect(|(pid, proc)| debug!("[{}] status: {}", pid, (proc.status()))) 
+    let mut ports = HashSet::new();
+    for p in procs {
+        match procfs::socket_inodes(p.pid()) {
+            Ok(inodes) => ports.extend(inodes.iter().filter_map(|inode| peers.get(inode).copied())),
+            // exited since the last refresh
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error = Some(format!("pid {}: {}", p.pid(), e)),
+        }
+    }
+    (ports, error)
+}
 
-            // filter out non active and dead processes
-            .filter(|(_, proc)| {
-                !matches!(proc.status(), ProcessStatus::Stop | ProcessStatus::Dead | ProcessStatus::Zombie)
-            })
-            .map(|(_, proc)| proc.pid().into())
-            .collect();
+/// Children of every process, from the parent links
+fn children_map(info: &sysinfo::System) -> HashMap<Pid, Vec<&sysinfo::Process>> {
+    let mut children: HashMap<Pid, Vec<&sysinfo::Process>> = HashMap::new();
+    info.processes()
+        .values()
+        .filter(|proc| !is_thread(proc))
+        .for_each(|proc| {
+            if let Some(ppid) = proc.parent() {
+                children.entry(ppid).or_default().push(proc);
+            }
+        });
+    children
+}
 
-        debug!("<{}> detected pids: {}", self.matcher, self.pids.len());
-        // trace matched pids
+/// Userland threads are listed along processes with their process as parent
+fn is_thread(proc: &sysinfo::Process) -> bool {
+    proc.thread_kind() == Some(ThreadKind::Userland)
+}
 
-        if log_enabled!(log::Level::Trace) {
-            self.pids
-                .iter()
-                .filter_map(|pid| info.processes().get(&Pid::from(*pid)))
-                .for_each(|p| trace!("- {}: \n {:#?}", p.pid(), p));
-        }
+/// Whether the process is pswatch itself or was spawned by one of its commands.
+/// The environment is read from `/proc` on demand, only for the processes already matched.
+fn is_self(proc: &sysinfo::Process) -> bool {
+    let self_pid = std::process::id();
+    proc.pid().as_u32() == self_pid
+        || procfs::env_var(proc.pid(), SPAWNED_BY_ENV)
+            .ok()
+            .flatten()
+            .is_some_and(|pid| pid == self_pid.to_string())
+}
 
-        self.lifetime.prev_refresh = self.lifetime.last_refresh;
-        self.lifetime.last_refresh = Some(t_refresh);
+impl StateTracker for Process {
+    type State = ProcState;
 
-        self.update_inner_state();
-        self.lifetime.state.clone()
+    /// updates the state and return a copy of the new state
+    fn update_state(&mut self, info: &sysinfo::System, t_refresh: Instant) -> ProcState {
+        let pids = self.match_pids(info);
+        self.track(pids, info, t_refresh)
     }
 
     fn state(&self) -> Self::State {
@@ -198,11 +1395,106 @@ impl ConditionMatcher for Process {
     type Condition = ProcCondition;
 
     fn matches(&self, c: Self::Condition) -> bool {
-        self.lifetime.matches(c)
+        match c {
+            ProcCondition::Seen(_) | ProcCondition::NotSeen(_) | ProcCondition::Flapping { .. } => {
+                self.lifetime.matches(c)
+            }
+            ProcCondition::CountAbove { count, span } => self.history.sustained(span, |s| s.count > count),
+            ProcCondition::CountBelow { count, span } => self.history.sustained(span, |s| s.count < count),
+            ProcCondition::CpuAbove { percent, span, aggregate } => {
+                self.history.sustained(span, |s| s.cpu.get(aggregate) > percent)
+            }
+            ProcCondition::CpuBelow { percent, span, aggregate } => {
+                self.history.sustained(span, |s| s.count > 0 && s.cpu.get(aggregate) < percent)
+            }
+            ProcCondition::MemoryAbove { bytes, span, aggregate, kind } => {
+                self.history.sustained(span, |s| s.memory(kind, aggregate) > bytes)
+            }
+            ProcCondition::MemoryBelow { bytes, span, aggregate, kind } => {
+                self.history.sustained(span, |s| s.count > 0 && s.memory(kind, aggregate) < bytes)
+            }
+            ProcCondition::IoReadAbove { rate, span, aggregate } => {
+                self.history.sustained(span, |s| s.io_read.get(aggregate) > rate)
+            }
+            ProcCondition::IoWriteAbove { rate, span, aggregate } => {
+                self.history.sustained(span, |s| s.io_write.get(aggregate) > rate)
+            }
+            ProcCondition::CpuTimeAbove { time, aggregate } => {
+                self.history.sustained(Duration::ZERO, |s| s.cpu_time.get(aggregate) > time)
+            }
+            ProcCondition::AgeAbove(age) => self.history.sustained(Duration::ZERO, |s| s.age > age),
+            ProcCondition::Status { statuses, span } => {
+                self.history.latest().is_some_and(|s| s.in_status(&statuses, span))
+            }
+            ProcCondition::FdsAbove { count, span, aggregate } => {
+                self.history.sustained(span, |s| s.fds.get(aggregate) > count)
+            }
+            ProcCondition::ThreadsAbove { count, span, aggregate } => {
+                self.history.sustained(span, |s| s.threads.get(aggregate) > count)
+            }
+            ProcCondition::ChildrenAbove { count, span, aggregate } => {
+                self.history.sustained(span, |s| s.children.get(aggregate) > count)
+            }
+            ProcCondition::ConnectedTo { port, span } => {
+                self.history.sustained(span, |s| s.connections.contains(&port))
+            }
+            ProcCondition::MemoryGrowth { bytes, per, aggregate } => self
+                .history
+                .memory_growth(per, aggregate)
+                .is_some_and(|slope| slope * per.as_secs_f64() > bytes as f64),
+            ProcCondition::Started => !self.started.is_empty(),
+            ProcCondition::Exited => !self.exited.is_empty(),
+        }
     }
 
     fn partial_match(&self, c: Self::Condition) -> Option<bool> {
-        self.lifetime.partial_match(c)
+        match c {
+            ProcCondition::Seen(_) | ProcCondition::NotSeen(_) | ProcCondition::Flapping { .. } => {
+                self.lifetime.partial_match(c)
+            }
+            ProcCondition::CountAbove { count, .. } => Some(self.pids.len() > count),
+            ProcCondition::CountBelow { count, .. } => Some(self.pids.len() < count),
+            ProcCondition::CpuAbove { percent, aggregate, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.cpu.get(aggregate) > percent))
+            }
+            ProcCondition::CpuBelow { percent, aggregate, .. } => Some(
+                self.history
+                    .latest()
+                    .is_some_and(|s| s.count > 0 && s.cpu.get(aggregate) < percent),
+            ),
+            ProcCondition::MemoryAbove { bytes, aggregate, kind, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.memory(kind, aggregate) > bytes))
+            }
+            ProcCondition::MemoryBelow { bytes, aggregate, kind, .. } => Some(
+                self.history
+                    .latest()
+                    .is_some_and(|s| s.count > 0 && s.memory(kind, aggregate) < bytes),
+            ),
+            ProcCondition::IoReadAbove { rate, aggregate, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.io_read.get(aggregate) > rate))
+            }
+            ProcCondition::IoWriteAbove { rate, aggregate, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.io_write.get(aggregate) > rate))
+            }
+            ProcCondition::CpuTimeAbove { .. } | ProcCondition::AgeAbove(_) => Some(self.matches(c)),
+            ProcCondition::Status { statuses, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.in_status(&statuses, Duration::ZERO)))
+            }
+            ProcCondition::MemoryGrowth { .. } => Some(self.matches(c)),
+            ProcCondition::ConnectedTo { port, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.connections.contains(&port)))
+            }
+            ProcCondition::FdsAbove { count, aggregate, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.fds.get(aggregate) > count))
+            }
+            ProcCondition::ThreadsAbove { count, aggregate, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.threads.get(aggregate) > count))
+            }
+            ProcCondition::ChildrenAbove { count, aggregate, .. } => {
+                Some(self.history.latest().is_some_and(|s| s.children.get(aggregate) > count))
+            }
+            ProcCondition::Started | ProcCondition::Exited => None,
+        }
     }
 }
 
@@ -234,6 +1526,11 @@ impl ConditionMatcher for ProcLifetime {
                         && self.prev_refresh.unwrap().elapsed() > span
                 }
             }
+            ProcCondition::Flapping { restarts, within } => {
+                self.restarts.iter().filter(|t| t.elapsed() <= within).count() >= restarts
+            }
+            // conditions on the matched processes are not tracked by the lifetime
+            _ => false,
         }
     }
 
@@ -244,6 +1541,8 @@ impl ConditionMatcher for ProcLifetime {
                 self.state,
                 ProcState::NotSeen | ProcState::NeverSeen
             )),
+            ProcCondition::Flapping { .. } => Some(self.matches(cond)),
+            _ => None,
         }
     }
 }
@@ -253,135 +1552,594 @@ impl ConditionMatcher for ProcLifetime {
 #[allow(unused_imports)]
 mod test {
     use super::*;
-    use crate::{matching::PatternIn, sched::Scheduler, state::*};
+    use crate::{matching::{Glob, PatternIn}, sched::Scheduler, state::*};
     use mock_instant::thread_local::MockClock;
     use regex::Regex;
     use sysinfo::System;
 
-    #[test]
-    fn default_process() {
-        let p = Process::from_pattern(PatternIn::Name("foo".to_string()));
-        assert!(matches!(p.state(), ProcState::NeverSeen))
+    #[test]
+    fn default_process() {
+        let p = Process::from_pattern(PatternIn::Name("foo".to_string()));
+        assert!(matches!(p.state(), ProcState::NeverSeen))
+    }
+
+    // default process matching on process name
+    #[test]
+    fn match_process_name() -> anyhow::Result<(), std::io::Error> {
+        let pattern = "aPYe1K";
+        let mut target = std::process::Command::new("tests/fake_bins/proc-50aPYe1K.sh")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(PatternIn::Name(pattern.to_string()));
+        let mut p_does_not_match =
+            Process::from_pattern(PatternIn::Name("foobar_234324".to_string()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+
+        p_match.update_state(&sys, Instant::now());
+        // dbg!(sys.processes().get(&p_match.pids[0].into()));
+        assert!(!p_match.pids.is_empty());
+
+        p_does_not_match.update_state(&sys, Instant::now());
+        assert!(p_does_not_match.pids.is_empty());
+
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
+
+    #[test]
+    fn match_pattern_exe() -> anyhow::Result<(), std::io::Error> {
+        let pattern = "fake_bins/sleep-w61Z";
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-w61Z")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(PatternIn::ExePath(pattern.to_string()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p_match.update_state(&sys, Instant::now());
+        assert!(!p_match.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
+
+    // exe path dereferences the os symlink so exe path here is the target of the symlink
+    #[test]
+    fn regex_pattern_exe() -> anyhow::Result<(), std::io::Error> {
+        let pattern = r"sl\w+-w\d{2}Z$"; // Example regex for files ending with .sh
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-583a")
+            .arg("5")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(PatternIn::ExePath(Regex::new(pattern).unwrap()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p_match.update_state(&sys, Instant::now());
+        assert!(!p_match.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ()) // Ensure you handle the Result from kill properly
+    }
+
+    #[test]
+    fn glob_pattern_exe() -> anyhow::Result<(), std::io::Error> {
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-w61Z")
+            .arg("5")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(PatternIn::ExePath(Glob::path("**/fake_bins/sleep-w??Z", false).unwrap()));
+        // globs are anchored
+        let mut p_partial = Process::from_pattern(PatternIn::ExePath(Glob::path("**/fake_bins/sleep", false).unwrap()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p_match.update_state(&sys, Instant::now());
+        assert!(p_match.pids.contains(&(target.id() as usize)));
+        p_partial.update_state(&sys, Instant::now());
+        assert!(p_partial.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
+
+    // regex for process name
+    #[test]
+    fn regex_pattern_name() -> anyhow::Result<(), std::io::Error> {
+        let pattern = r"sleep-\d{3}a$"; // Example regex for files ending with .sh
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-583a")
+            .arg("5")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(PatternIn::Name(Regex::new(pattern).unwrap()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p_match.update_state(&sys, Instant::now());
+        assert!(!p_match.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ()) // Ensure you handle the Result from kill properly
+    }
+
+    // the kernel truncates the process name to 15 bytes: `sleep-long-name`
+    #[test]
+    fn match_truncated_name() -> anyhow::Result<(), std::io::Error> {
+        use crate::matching::{MatchMode, StrPattern};
+
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-long-name-t4Xq9")
+            .arg("5")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_full = Process::from_pattern(PatternIn::Name(Regex::new("^sleep-long-name-t4Xq9$").unwrap()));
+        let mut p_truncated = Process::from_pattern(PatternIn::Name(StrPattern::new("sleep-long-name", MatchMode::Exact, false)));
+        let mut p_other = Process::from_pattern(PatternIn::Name(StrPattern::new("sleep-long-name-x", MatchMode::Prefix, false)));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+
+        assert_eq!(sys.process(Pid::from_u32(target.id())).unwrap().name(), "sleep-long-name");
+        p_full.update_state(&sys, Instant::now());
+        assert_eq!(p_full.pids, vec![target.id() as usize]);
+        p_truncated.update_state(&sys, Instant::now());
+        assert_eq!(p_truncated.pids, vec![target.id() as usize]);
+        p_other.update_state(&sys, Instant::now());
+        assert!(p_other.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
+
+    // regex for process cmdline
+    #[test]
+    fn regex_pattern_cmdline() -> anyhow::Result<(), std::io::Error> {
+        let pattern = r"sleep-\d{3}a\s5"; // Example regex for files ending with .sh
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-583a")
+            .arg("5")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(PatternIn::Cmdline(Regex::new(pattern).unwrap()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p_match.update_state(&sys, Instant::now());
+        assert!(!p_match.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ()) // Ensure you handle the Result from kill properly
+    }
+
+    #[test]
+    fn match_pattern_cmdline() -> anyhow::Result<(), std::io::Error> {
+        let pattern = "300";
+        let mut target = std::process::Command::new("tests/fake_bins/proc-Ml51n.sh")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(PatternIn::Cmdline(pattern.to_string()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p_match.update_state(&sys, Instant::now());
+        assert!(!p_match.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
+
+    #[test]
+    fn match_composite_pattern() -> anyhow::Result<(), std::io::Error> {
+        let mut target = std::process::Command::new("tests/fake_bins/proc-89MLx.sh")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut p_match = Process::from_pattern(ProcessMatcher::All(vec![
+            PatternIn::Cmdline("89MLx".to_string()).into(),
+            PatternIn::Name(Regex::new(r"^proc-\w+\.sh$").unwrap()).into(),
+        ]));
+        let mut p_not = Process::from_pattern(ProcessMatcher::All(vec![
+            PatternIn::Cmdline("89MLx".to_string()).into(),
+            ProcessMatcher::Not(Box::new(PatternIn::Name("89MLx".to_string()).into())),
+        ]));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p_match.update_state(&sys, Instant::now());
+        assert!(p_match.pids.contains(&(target.id() as usize)));
+        p_not.update_state(&sys, Instant::now());
+        assert!(p_not.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
+
+    #[test]
+    fn match_owner() -> anyhow::Result<(), std::io::Error> {
+        use crate::matching::Owner;
+
+        let mut target = std::process::Command::new("tests/fake_bins/proc-dZWv6.sh")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let uid = **System::new_all()
+            .process(Pid::from_u32(target.id()))
+            .and_then(|p| p.effective_user_id())
+            .unwrap();
+
+        let owned_by = |uid| {
+            Process::from_pattern(ProcessMatcher::All(vec![
+                PatternIn::Cmdline("dZWv6".to_string()).into(),
+                ProcessMatcher::Owner(Owner::User { uid, effective: true }),
+            ]))
+        };
+        let mut p_match = owned_by(uid);
+        let mut p_other = owned_by(uid + 1);
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::refresh_specs_for([&p_match]));
+        p_match.update_state(&sys, Instant::now());
+        assert!(p_match.pids.contains(&(target.id() as usize)));
+        p_other.update_state(&sys, Instant::now());
+        assert!(p_other.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
+
+    // the fake bin script forks a `sleep` child
+    #[test]
+    fn match_ancestor() -> anyhow::Result<(), std::io::Error> {
+        let mut target = std::process::Command::new("tests/fake_bins/proc-g9wey.sh")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let child_of = |depth| {
+            Process::from_pattern(ProcessMatcher::All(vec![
+                PatternIn::Name("sleep".to_string()).into(),
+                ProcessMatcher::Ancestor {
+                    matcher: Box::new(PatternIn::Cmdline(Regex::new(r"proc-g9wey\.sh").unwrap()).into()),
+                    depth,
+                },
+            ]))
+        };
+        let mut p_parent = child_of(Some(1));
+        let mut p_ancestor = child_of(None);
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+
+        p_parent.update_state(&sys, Instant::now());
+        assert_eq!(p_parent.pids.len(), 1);
+        let child = sys.process(Pid::from(p_parent.pids[0])).unwrap();
+        assert_eq!(child.parent(), Some(Pid::from_u32(target.id())));
+
+        p_ancestor.update_state(&sys, Instant::now());
+        assert_eq!(p_ancestor.pids, p_parent.pids);
+
+        let _ = target.kill().and_then(|_| target.wait());
+        // kill the orphaned child
+        if let Some(child) = sys.process(Pid::from(p_parent.pids[0])) {
+            child.kill();
+        }
+        Ok(())
     }
 
-    // default process matching on process name
     #[test]
-    fn match_process_name() -> anyhow::Result<(), std::io::Error> {
-        let pattern = "aPYe1K";
-        let mut target = std::process::Command::new("tests/fake_bins/proc-50aPYe1K.sh")
+    fn match_cwd() -> anyhow::Result<(), std::io::Error> {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut target = std::process::Command::new(root.join("tests/fake_bins/proc-lG579.sh"))
             .arg("300")
+            .current_dir(root.join("tests"))
             .stdout(std::process::Stdio::null())
             .spawn()
             .unwrap();
         std::thread::sleep(Duration::from_secs(1));
 
-        let mut p_match = Process::from_pattern(PatternIn::Name(pattern.to_string()));
-        let mut p_does_not_match =
-            Process::from_pattern(PatternIn::Name("foobar_234324".to_string()));
+        let in_cwd = |m: ProcessMatcher| {
+            Process::from_pattern(ProcessMatcher::All(vec![
+                PatternIn::Cmdline("lG579".to_string()).into(),
+                m,
+            ]))
+        };
+        let mut p_under = in_cwd(ProcessMatcher::CwdUnder(root.to_path_buf()));
+        let mut p_regex = in_cwd(PatternIn::Cwd(Regex::new("/tests$").unwrap()).into());
+        // path prefix is matched by components
+        let mut p_not_under = in_cwd(ProcessMatcher::CwdUnder(root.join("test")));
         let mut sys = System::new();
         sys.refresh_specifics(Scheduler::process_refresh_specs());
 
-        p_match.update_state(&sys, Instant::now());
-        // dbg!(sys.processes().get(&p_match.pids[0].into()));
-        assert!(!p_match.pids.is_empty());
+        p_under.update_state(&sys, Instant::now());
+        assert!(p_under.pids.contains(&(target.id() as usize)));
+        p_regex.update_state(&sys, Instant::now());
+        assert!(p_regex.pids.contains(&(target.id() as usize)));
+        p_not_under.update_state(&sys, Instant::now());
+        assert!(p_not_under.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
+    }
 
-        p_does_not_match.update_state(&sys, Instant::now());
-        assert!(p_does_not_match.pids.is_empty());
+    #[test]
+    fn match_env() -> anyhow::Result<(), std::io::Error> {
+        let mut target = std::process::Command::new("tests/fake_bins/proc-oVB62.sh")
+            .arg("300")
+            .env("JOB_KIND", "nightly")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let with_env = |m: ProcessMatcher| {
+            Process::from_pattern(ProcessMatcher::All(vec![
+                PatternIn::Cmdline("oVB62".to_string()).into(),
+                m,
+            ]))
+        };
+        let mut p_value = with_env(PatternIn::Env("JOB_KIND".into(), "nightly".to_string()).into());
+        let mut p_regex = with_env(PatternIn::Env("JOB_KIND".into(), Regex::new("^weekly$").unwrap()).into());
+        let mut p_unset = with_env(ProcessMatcher::EnvSet("PSWATCH_oVB62".into()));
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::refresh_specs_for([&p_value]));
 
-        target.kill()
+        p_value.update_state(&sys, Instant::now());
+        assert!(p_value.pids.contains(&(target.id() as usize)));
+        p_regex.update_state(&sys, Instant::now());
+        assert!(p_regex.pids.is_empty());
+        p_unset.update_state(&sys, Instant::now());
+        assert!(p_unset.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
     }
 
     #[test]
-    fn match_pattern_exe() -> anyhow::Result<(), std::io::Error> {
-        let pattern = "fake_bins/sleep-w61Z";
-        let mut target = std::process::Command::new("tests/fake_bins/sleep-w61Z")
+    fn match_cgroup() -> anyhow::Result<(), std::io::Error> {
+        use crate::{matching::CgroupPart, procfs};
+
+        let mut target = std::process::Command::new("tests/fake_bins/proc-pgY5v.sh")
             .arg("300")
             .stdout(std::process::Stdio::null())
             .spawn()
             .unwrap();
         std::thread::sleep(Duration::from_secs(1));
+        let cgroup = procfs::cgroup(Pid::from_u32(target.id())).unwrap();
 
-        let mut p_match = Process::from_pattern(PatternIn::ExePath(pattern.to_string()));
+        let in_cgroup = |m: ProcessMatcher| {
+            Process::from_pattern(ProcessMatcher::All(vec![
+                PatternIn::Cmdline("pgY5v".to_string()).into(),
+                m,
+            ]))
+        };
+        let same_cgroup = format!("^{}$", regex::escape(&cgroup));
+        let mut p_match = in_cgroup(PatternIn::Cgroup(CgroupPart::Path, Regex::new(&same_cgroup).unwrap()).into());
+        let mut p_other = in_cgroup(PatternIn::Cgroup(CgroupPart::Path, "pswatch-pgY5v.scope".to_string()).into());
         let mut sys = System::new();
         sys.refresh_specifics(Scheduler::process_refresh_specs());
+
         p_match.update_state(&sys, Instant::now());
-        assert!(!p_match.pids.is_empty());
-        target.kill()
+        assert!(p_match.pids.contains(&(target.id() as usize)));
+        p_other.update_state(&sys, Instant::now());
+        assert!(p_other.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
     }
 
-    // exe path dereferences the os symlink so exe path here is the target of the symlink
     #[test]
-    fn regex_pattern_exe() -> anyhow::Result<(), std::io::Error> {
-        let pattern = r"sl\w+-w\d{2}Z$"; // Example regex for files ending with .sh
-        let mut target = std::process::Command::new("tests/fake_bins/sleep-583a")
-            .arg("5")
+    fn match_args() -> anyhow::Result<(), std::io::Error> {
+        use crate::matching::{ArgPosition, MatchMode, StrPattern};
+
+        let mut target = std::process::Command::new("tests/fake_bins/proc-v5PEW.sh")
+            .args(["300", "--gpu", "two words"])
             .stdout(std::process::Stdio::null())
             .spawn()
             .unwrap();
         std::thread::sleep(Duration::from_secs(1));
 
-        let mut p_match = Process::from_pattern(PatternIn::ExePath(Regex::new(pattern).unwrap()));
+        let exact = |s: &str| StrPattern::new(s, MatchMode::Exact, false);
+        let with_arg = |m: ProcessMatcher| {
+            Process::from_pattern(ProcessMatcher::All(vec![
+                PatternIn::Cmdline("v5PEW".to_string()).into(),
+                m,
+            ]))
+        };
+        let mut p_any = with_arg(PatternIn::Arg(ArgPosition::Any, exact("two words")).into());
+        let mut p_index = with_arg(PatternIn::Arg(ArgPosition::Index(3), exact("--gpu")).into());
+        // arguments are not split on spaces
+        let mut p_split = with_arg(PatternIn::Arg(ArgPosition::Any, exact("two")).into());
+        let mut p_wrong_index = with_arg(PatternIn::Arg(ArgPosition::Index(2), exact("--gpu")).into());
         let mut sys = System::new();
         sys.refresh_specifics(Scheduler::process_refresh_specs());
-        p_match.update_state(&sys, Instant::now());
-        assert!(!p_match.pids.is_empty());
-        target.kill() // Ensure you handle the Result from kill properly
+
+        // the script is run by the shell: argv = [sh, script, 300, --gpu, "two words"]
+        p_any.update_state(&sys, Instant::now());
+        assert!(p_any.pids.contains(&(target.id() as usize)));
+        p_index.update_state(&sys, Instant::now());
+        assert!(p_index.pids.contains(&(target.id() as usize)));
+        p_split.update_state(&sys, Instant::now());
+        assert!(p_split.pids.is_empty());
+        p_wrong_index.update_state(&sys, Instant::now());
+        assert!(p_wrong_index.pids.is_empty());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
     }
 
-    // regex for process name
     #[test]
-    fn regex_pattern_name() -> anyhow::Result<(), std::io::Error> {
-        let pattern = r"sleep-\d{3}a$"; // Example regex for files ending with .sh
-        let mut target = std::process::Command::new("tests/fake_bins/sleep-583a")
+    fn match_pidfile() -> anyhow::Result<(), std::io::Error> {
+        use crate::matching::{Pidfile, StrPattern};
+
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-w61Z")
             .arg("5")
             .stdout(std::process::Stdio::null())
             .spawn()
             .unwrap();
         std::thread::sleep(Duration::from_secs(1));
 
-        let mut p_match = Process::from_pattern(PatternIn::Name(Regex::new(pattern).unwrap()));
+        let path = std::env::temp_dir().join(format!("pswatch-test-{}.pid", target.id()));
+        let with_exe = |exe: &str| {
+            ProcessMatcher::All(vec![
+                ProcessMatcher::Pidfile(Pidfile::new(&path)),
+                PatternIn::ExePath(StrPattern::from(exe.to_string())).into(),
+            ])
+        };
+        let mut p_match = Process::from_pattern(with_exe("sleep-w61Z"));
+        let mut p_other_exe = Process::from_pattern(with_exe("sleep-XXXX"));
         let mut sys = System::new();
         sys.refresh_specifics(Scheduler::process_refresh_specs());
+
+        // missing pidfile
+        p_match.update_state(&sys, Instant::now());
+        assert!(p_match.pids.is_empty());
+
+        std::fs::write(&path, format!("{}\n", target.id())).unwrap();
+        p_match.update_state(&sys, Instant::now());
+        assert_eq!(p_match.pids, vec![target.id() as usize]);
+        p_other_exe.update_state(&sys, Instant::now());
+        assert!(p_other_exe.pids.is_empty());
+
+        // stale pidfile
+        target.kill().and_then(|_| target.wait())?;
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
         p_match.update_state(&sys, Instant::now());
-        assert!(!p_match.pids.is_empty());
-        target.kill() // Ensure you handle the Result from kill properly
+        assert!(p_match.pids.is_empty());
+        std::fs::remove_file(&path)
     }
 
-    // regex for process cmdline
+    // the fake bin script forks a `sleep` child
     #[test]
-    fn regex_pattern_cmdline() -> anyhow::Result<(), std::io::Error> {
-        let pattern = r"sleep-\d{3}a\s5"; // Example regex for files ending with .sh
-        let mut target = std::process::Command::new("tests/fake_bins/sleep-583a")
-            .arg("5")
+    fn match_descendants() -> anyhow::Result<(), std::io::Error> {
+        let mut target = std::process::Command::new("tests/fake_bins/proc-Xk3Tz.sh")
+            .arg("300")
             .stdout(std::process::Stdio::null())
             .spawn()
             .unwrap();
         std::thread::sleep(Duration::from_secs(1));
 
-        let mut p_match = Process::from_pattern(PatternIn::Cmdline(Regex::new(pattern).unwrap()));
+        let matching = || PatternIn::Cmdline(Regex::new(r"proc-Xk3Tz\.sh").unwrap());
+        let mut p_tree = Process::from_pattern(matching()).with_descendants(true);
+        let mut p_root = Process::from_pattern(matching());
+        // threads of the test harness are not counted as processes
+        let own_exe = std::env::current_exe().unwrap().to_string_lossy().into_owned();
+        let mut p_threads = Process::from_pattern(PatternIn::ExePath(Glob::path(&own_exe, false).unwrap()));
+        let mut sys = System::new();
+        // wait for the script to fork its child
+        for _ in 0..10 {
+            sys.refresh_specifics(Scheduler::process_refresh_specs());
+            if sys.processes().values().any(|proc| proc.parent() == Some(Pid::from_u32(target.id()))) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+
+        p_root.update_state(&sys, Instant::now());
+        assert_eq!(p_root.pids, vec![target.id() as usize]);
+        p_tree.update_state(&sys, Instant::now());
+        assert_eq!(p_tree.pids.len(), 2);
+        assert_eq!(p_tree.pids[0], target.id() as usize);
+        let child = sys.process(Pid::from(p_tree.pids[1])).unwrap();
+        assert_eq!(child.parent(), Some(Pid::from_u32(target.id())));
+        p_threads.update_state(&sys, Instant::now());
+        assert_eq!(p_threads.pids, vec![std::process::id() as usize]);
+
+        let _ = target.kill().and_then(|_| target.wait());
+        child.kill();
+        Ok(())
+    }
+
+    #[test]
+    fn match_listening() -> anyhow::Result<(), std::io::Error> {
+        use crate::matching::Listening;
+        use std::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = SocketPort { proto: Proto::Tcp, port: listener.local_addr()?.port() };
+        let connected = ProcCondition::ConnectedTo { port, span: Duration::ZERO };
+        let mut p = Process::build(ProcessMatcher::Listening(Listening::new(port)), ProcLifetime::new())
+            .with_procfs_metrics(connected.procfs_metrics(ProcfsMetrics::default()));
         let mut sys = System::new();
         sys.refresh_specifics(Scheduler::process_refresh_specs());
-        p_match.update_state(&sys, Instant::now());
-        assert!(!p_match.pids.is_empty());
-        target.kill() // Ensure you handle the Result from kill properly
+
+        p.update_state(&sys, Instant::now());
+        assert_eq!(p.pids, vec![std::process::id() as usize]);
+        assert!(!p.matches(connected.clone()));
+
+        let _stream = TcpStream::connect(listener.local_addr()?)?;
+        p.update_state(&sys, Instant::now());
+        assert!(p.matches(connected.clone()));
+        assert!(!p.matches(ProcCondition::ConnectedTo { port: SocketPort { proto: Proto::Udp, ..port }, span: Duration::ZERO }));
+
+        drop(listener);
+        p.update_state(&sys, Instant::now());
+        assert!(p.pids.is_empty());
+        Ok(())
     }
 
     #[test]
-    fn match_pattern_cmdline() -> anyhow::Result<(), std::io::Error> {
-        let pattern = "300";
-        let mut target = std::process::Command::new("tests/fake_bins/proc-Ml51n.sh")
+    fn match_listening_workers() -> std::io::Result<()> {
+        use crate::matching::Listening;
+        use std::net::TcpListener;
+        use std::os::{fd::AsRawFd, unix::process::CommandExt};
+
+        // a worker sharing the listening socket, like the workers of a pre-fork server
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let fd = listener.as_raw_fd();
+        let mut command = std::process::Command::new("tests/fake_bins/sleep-w61Z");
+        command.arg("300");
+        // SAFETY: fcntl is async signal safe, clearing close on exec keeps the socket open in the worker
+        unsafe {
+            command.pre_exec(move || match libc::fcntl(fd, libc::F_SETFD, 0) {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            })
+        };
+        let mut worker = command.spawn()?;
+
+        let port = SocketPort { proto: Proto::Tcp, port: listener.local_addr()?.port() };
+        let mut p = Process::build(ProcessMatcher::Listening(Listening::new(port)), ProcLifetime::new());
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        p.update_state(&sys, Instant::now());
+        let mut pids = p.pids.clone();
+        pids.sort();
+        assert_eq!(pids, vec![std::process::id() as usize, worker.id() as usize]);
+
+        worker.kill().and_then(|_| worker.wait()).map(|_| ())
+    }
+
+    #[test]
+    fn match_exclude() -> std::io::Result<()> {
+        let mut target = std::process::Command::new("tests/fake_bins/proc-rELGb.sh")
             .arg("300")
             .stdout(std::process::Stdio::null())
             .spawn()
             .unwrap();
+        // same as a command run by the scheduler
+        let mut spawned = std::process::Command::new("tests/fake_bins/proc-rELGb.sh")
+            .arg("301")
+            .env(SPAWNED_BY_ENV, std::process::id().to_string())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
         std::thread::sleep(Duration::from_secs(1));
 
-        let mut p_match = Process::from_pattern(PatternIn::Cmdline(pattern.to_string()));
+        let matching = || PatternIn::Cmdline("rELGb".to_string());
+        let mut p_all = Process::from_pattern(matching()).with_exclude_self(false);
+        let mut p_self = Process::from_pattern(matching()).with_exclude_self(true);
+        let mut p_exclude = Process::from_pattern(matching())
+            .with_exclude(vec![PatternIn::Cmdline("rELGb.sh 300".to_string()).into()]);
         let mut sys = System::new();
-        sys.refresh_specifics(Scheduler::process_refresh_specs());
-        p_match.update_state(&sys, Instant::now());
-        assert!(!p_match.pids.is_empty());
-        target.kill()
+        sys.refresh_specifics(Scheduler::refresh_specs_for([&p_self]));
+
+        p_all.update_state(&sys, Instant::now());
+        assert!(p_all.pids.contains(&(target.id() as usize)));
+        assert!(p_all.pids.contains(&(spawned.id() as usize)));
+        p_self.update_state(&sys, Instant::now());
+        assert_eq!(p_self.pids, vec![target.id() as usize]);
+        p_exclude.update_state(&sys, Instant::now());
+        assert_eq!(p_exclude.pids, vec![spawned.id() as usize]);
+
+        let _ = spawned.kill().and_then(|_| spawned.wait());
+        target.kill().and_then(|_| target.wait()).map(|_| ())
     }
 
     #[test]
@@ -447,6 +2205,525 @@ mod test {
         assert!(p.lifetime.first_seen.unwrap() == first_seen.unwrap());
     }
 
+    #[test]
+    fn cond_count() {
+        MockClock::set_time(Duration::ZERO);
+        let above = ProcCondition::CountAbove { count: 2, span: Duration::from_secs(5) };
+        let below = ProcCondition::CountBelow { count: 2, span: Duration::ZERO };
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()))
+            .with_retention(Duration::from_secs(5));
+
+        refresh_pids(&mut p, vec![1]);
+        assert!(p.matches(below.clone()));
+        assert!(!p.matches(above.clone()));
+
+        // above the count but not for long enough
+        refresh_pids(&mut p, vec![1, 2, 3]);
+        refresh_pids(&mut p, vec![1, 2, 3]);
+        assert!(!p.matches(above.clone()));
+        assert_eq!(p.partial_match(above.clone()), Some(true));
+        refresh_pids(&mut p, vec![1, 2, 3, 4]);
+        assert!(p.matches(above.clone()));
+        refresh_pids(&mut p, vec![1, 2, 3]);
+        assert!(p.matches(above.clone()));
+        assert!(!p.matches(below.clone()));
+
+        // old samples are dropped
+        assert!(p.history.samples.len() <= 4);
+
+        // dropping below the count resets the span
+        refresh_pids(&mut p, vec![1, 2]);
+        refresh_pids(&mut p, vec![1, 2, 3]);
+        assert!(!p.matches(above.clone()));
+    }
+
+    /// Samples built from the values of each process, the count is the number of values
+    impl Sample {
+        fn with_cpu(mut self, cpu: &[f32]) -> Self {
+            self.count = cpu.len();
+            self.cpu = Usage::of(cpu.iter().copied());
+            self
+        }
+
+        /// resident memory of the pids 1, 2 ...
+        fn with_memory(mut self, rss: &[u64]) -> Self {
+            self.count = rss.len();
+            self.memory = Usage::of(rss.iter().copied());
+            self.rss = (1..).zip(rss.iter().copied()).collect();
+            self
+        }
+
+        fn with_virtual_memory(mut self, vsz: &[u64]) -> Self {
+            self.count = vsz.len();
+            self.virtual_memory = Usage::of(vsz.iter().copied());
+            self
+        }
+
+        /// bytes read and written since they started by the pids 1, 2 ...
+        fn with_io(mut self, totals: &[(u64, u64)]) -> Self {
+            self.count = totals.len();
+            self.io_totals = (1..)
+                .zip(totals)
+                .map(|(pid, &(read, written))| (pid, IoTotals { read, written }))
+                .collect();
+            self
+        }
+
+        /// statuses of the pids 1, 2 ...
+        fn with_statuses(mut self, statuses: &[ProcStatus]) -> Self {
+            self.count = statuses.len();
+            self.statuses = (1..)
+                .zip(statuses)
+                .map(|(pid, &status)| (pid, StatusSince { status, since: self.time }))
+                .collect();
+            self
+        }
+
+        fn with_fds(mut self, fds: &[usize]) -> Self {
+            self.count = fds.len();
+            self.fds = Usage::of(fds.iter().copied());
+            self
+        }
+
+        fn with_threads(mut self, threads: &[usize]) -> Self {
+            self.count = threads.len();
+            self.threads = Usage::of(threads.iter().copied());
+            self
+        }
+
+        fn with_children(mut self, children: &[usize]) -> Self {
+            self.count = children.len();
+            self.children = Usage::of(children.iter().copied());
+            self
+        }
+    }
+
+    /// Pushes `sample` to the history as a refresh would and moves the clock 2s forward
+    fn refresh(p: &mut Process, mut sample: Sample) {
+        if let Some(prev) = p.history.latest() {
+            sample.update_io_rates(prev);
+            sample.update_statuses(prev);
+        }
+        p.history.push(sample);
+        MockClock::advance(Duration::from_secs(2));
+    }
+
+    /// Tracks `pids` as the matched processes and moves the clock 2s forward
+    fn refresh_pids(p: &mut Process, pids: Vec<usize>) {
+        p.track(pids, &System::new(), Instant::now());
+        MockClock::advance(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn cond_cpu() {
+        MockClock::set_time(Duration::ZERO);
+        let above = |aggregate| ProcCondition::CpuAbove { percent: 90.0, span: Duration::from_secs(4), aggregate };
+        let below = ProcCondition::CpuBelow { percent: 5.0, span: Duration::ZERO, aggregate: Aggregate::Sum };
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()))
+            .with_retention(Duration::from_secs(4));
+
+        // no process is not idle
+        refresh(&mut p, Sample::default());
+        assert!(!p.matches(below.clone()));
+        refresh(&mut p, Sample::default().with_cpu(&[1.0, 2.0]));
+        assert!(p.matches(below.clone()));
+
+        refresh(&mut p, Sample::default().with_cpu(&[60.0, 40.0]));
+        refresh(&mut p, Sample::default().with_cpu(&[70.0, 30.0]));
+        assert!(!p.matches(below.clone()));
+        assert!(p.matches(above(Aggregate::Sum)));
+        assert!(!p.matches(above(Aggregate::Max)));
+        assert_eq!(p.partial_match(above(Aggregate::Sum)), Some(true));
+
+        refresh(&mut p, Sample::default().with_cpu(&[95.0, 1.0]));
+        refresh(&mut p, Sample::default().with_cpu(&[100.0]));
+        assert!(p.matches(above(Aggregate::Max)));
+        // cpu usage is only refreshed when needed
+        assert!(below.refresh_kind(ProcessRefreshKind::new()).cpu());
+        assert!(!ProcCondition::Seen(Duration::ZERO).refresh_kind(ProcessRefreshKind::new()).cpu());
+    }
+
+    #[test]
+    fn cond_memory() {
+        MockClock::set_time(Duration::ZERO);
+        const GIB: u64 = 1 << 30;
+        let above = |kind| ProcCondition::MemoryAbove {
+            bytes: 2 * GIB,
+            span: Duration::from_secs(4),
+            aggregate: Aggregate::Sum,
+            kind,
+        };
+        let below = ProcCondition::MemoryBelow {
+            bytes: GIB,
+            span: Duration::ZERO,
+            aggregate: Aggregate::Max,
+            kind: MemoryKind::Rss,
+        };
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()))
+            .with_retention(Duration::from_secs(4));
+        let usage = |rss: &[u64], vsz: &[u64]| Sample::default().with_memory(rss).with_virtual_memory(vsz);
+
+        refresh(&mut p, usage(&[GIB / 2, GIB / 2], &[GIB, GIB]));
+        assert!(p.matches(below.clone()));
+        assert!(!p.matches(above(MemoryKind::Rss)));
+        assert!(!p.matches(above(MemoryKind::Virtual)));
+        refresh(&mut p, usage(&[GIB / 2, GIB], &[GIB, 2 * GIB]));
+        assert_eq!(p.partial_match(above(MemoryKind::Virtual)), Some(true));
+        assert!(!p.matches(above(MemoryKind::Virtual)));
+        assert!(!p.matches(below.clone()));
+
+        refresh(&mut p, usage(&[2 * GIB, GIB], &[4 * GIB, 2 * GIB]));
+        assert!(p.matches(above(MemoryKind::Virtual)));
+        assert!(!p.matches(above(MemoryKind::Rss)));
+        refresh(&mut p, usage(&[2 * GIB, GIB], &[4 * GIB, 2 * GIB]));
+        assert!(p.matches(above(MemoryKind::Rss)));
+        assert!(above(MemoryKind::Rss).refresh_kind(ProcessRefreshKind::new()).memory());
+    }
+
+    #[test]
+    fn cond_io() {
+        MockClock::set_time(Duration::ZERO);
+        const MIB: u64 = 1 << 20;
+        let read_above = |aggregate| ProcCondition::IoReadAbove { rate: 10 * MIB, span: Duration::from_secs(2), aggregate };
+        let write_above = ProcCondition::IoWriteAbove { rate: MIB, span: Duration::ZERO, aggregate: Aggregate::Sum };
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()))
+            .with_retention(Duration::from_secs(2));
+        // bytes read by pids 1 and 2 and written by pid 2 since they started
+        let io = |read: [u64; 2], written: u64| Sample::default().with_io(&[(read[0], 0), (read[1], written)]);
+
+        // no rate without a previous sample
+        refresh(&mut p, io([100 * MIB, 100 * MIB], 10 * MIB));
+        assert!(!p.matches(read_above(Aggregate::Sum)));
+
+        // 6MiB/s + 8MiB/s
+        refresh(&mut p, io([112 * MIB, 116 * MIB], 12 * MIB));
+        assert_eq!(p.history.latest().unwrap().io_read.total, 14 * MIB);
+        refresh(&mut p, io([124 * MIB, 132 * MIB], 14 * MIB));
+        assert!(p.matches(read_above(Aggregate::Sum)));
+        assert!(!p.matches(read_above(Aggregate::Max)));
+        assert!(!p.matches(write_above.clone()));
+
+        refresh(&mut p, io([124 * MIB, 172 * MIB], 18 * MIB));
+        assert!(p.matches(write_above.clone()));
+        assert!(write_above.refresh_kind(ProcessRefreshKind::new()).disk_usage());
+    }
+
+    #[test]
+    fn cond_cpu_time_and_age() {
+        const HOUR: Duration = Duration::from_secs(3600);
+        let cpu_time = |aggregate| ProcCondition::CpuTimeAbove { time: 2 * HOUR, aggregate };
+        let age = ProcCondition::AgeAbove(24 * HOUR);
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()));
+        assert!(!p.matches(age.clone()));
+
+        p.history.push(Sample {
+            cpu_time: Usage::of([HOUR, 90 * HOUR / 60].into_iter()),
+            age: 25 * HOUR,
+            ..Default::default()
+        });
+        assert!(p.matches(cpu_time(Aggregate::Sum)));
+        assert!(!p.matches(cpu_time(Aggregate::Max)));
+        assert!(p.matches(age.clone()));
+        assert_eq!(age.procfs_metrics(ProcfsMetrics::default()), ProcfsMetrics::default());
+        assert!(cpu_time(Aggregate::Sum).procfs_metrics(ProcfsMetrics::default()).cpu_time);
+    }
+
+    #[test]
+    fn cond_started_exited() {
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()));
+
+        // processes running on the first refresh were not started now
+        refresh_pids(&mut p, vec![1]);
+        assert!(!p.matches(ProcCondition::Started));
+
+        refresh_pids(&mut p, vec![1, 2]);
+        assert!(p.matches(ProcCondition::Started));
+        assert!(!p.matches(ProcCondition::Exited));
+        assert_eq!(p.edge_pids(&ProcCondition::Started), Some([2].as_slice()));
+
+        // edges fire only once
+        refresh_pids(&mut p, vec![1, 2]);
+        assert!(!p.matches(ProcCondition::Started));
+
+        refresh_pids(&mut p, vec![2, 3]);
+        assert_eq!(p.edge_pids(&ProcCondition::Started), Some([3].as_slice()));
+        assert_eq!(p.edge_pids(&ProcCondition::Exited), Some([1].as_slice()));
+
+        refresh_pids(&mut p, vec![]);
+        assert!(!p.matches(ProcCondition::Started));
+        let mut exited = p.edge_pids(&ProcCondition::Exited).unwrap().to_vec();
+        exited.sort();
+        assert_eq!(exited, vec![2, 3]);
+        assert_eq!(p.edge_pids(&ProcCondition::Seen(Duration::ZERO)), None);
+        assert_eq!(p.partial_match(ProcCondition::Exited), None);
+
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string())).with_report_running(true);
+        refresh_pids(&mut p, vec![1]);
+        assert_eq!(p.edge_pids(&ProcCondition::Started), Some([1].as_slice()));
+    }
+
+    #[test]
+    fn cond_flapping() {
+        MockClock::set_time(Duration::ZERO);
+        let flapping = ProcCondition::Flapping { restarts: 3, within: Duration::from_secs(10) };
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()))
+            .with_retention(flapping.span());
+
+        // first appearance is not a restart
+        refresh_pids(&mut p, vec![1]);
+        refresh_pids(&mut p, vec![]);
+        refresh_pids(&mut p, vec![2]);
+        assert!(!p.matches(flapping.clone()));
+
+        // a pid replaced while still seen
+        refresh_pids(&mut p, vec![3]);
+        refresh_pids(&mut p, vec![3, 4]);
+        assert!(!p.matches(flapping.clone()));
+        refresh_pids(&mut p, vec![]);
+        refresh_pids(&mut p, vec![5]);
+        assert!(p.matches(flapping.clone()));
+        assert_eq!(p.partial_match(flapping.clone()), Some(true));
+
+        // restarts out of the window are forgotten
+        MockClock::advance(Duration::from_secs(4));
+        refresh_pids(&mut p, vec![5]);
+        assert!(!p.matches(flapping.clone()));
+        assert_eq!(p.lifetime.restarts.len(), 1);
+    }
+
+    #[test]
+    fn flapping_ignores_descendants() {
+        let spawn = || {
+            std::process::Command::new("tests/fake_bins/sleep-Fl7Dc")
+                .arg("300")
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .unwrap()
+        };
+        let own_exe = std::env::current_exe().unwrap().to_string_lossy().into_owned();
+        let mut p = Process::from_pattern(PatternIn::ExePath(Glob::path(&own_exe, false).unwrap()))
+            .with_descendants(true)
+            .with_retention(Duration::from_secs(60));
+        let mut sys = System::new();
+
+        // the children are replaced while the matched process keeps running
+        let mut child = spawn();
+        for _ in 0..3 {
+            sys.refresh_specifics(Scheduler::process_refresh_specs());
+            p.update_state(&sys, Instant::now());
+            let _ = child.kill().and_then(|_| child.wait());
+            child = spawn();
+        }
+        assert!(p.lifetime.restarts.is_empty());
+
+        let _ = child.kill().and_then(|_| child.wait());
+    }
+
+    #[test]
+    fn cond_memory_growth() {
+        MockClock::set_time(Duration::ZERO);
+        const MIB: u64 = 1 << 20;
+        const GIB: u64 = 1 << 30;
+        let growth = ProcCondition::MemoryGrowth { bytes: 50 * MIB, per: Duration::from_secs(10), aggregate: Aggregate::Sum };
+        let new_process = || Process::from_pattern(PatternIn::Name("foo".to_string())).with_retention(growth.span());
+
+        // growing by 60MiB per 10s, split over two processes
+        let mut p = new_process();
+        for i in 0..4 {
+            refresh(&mut p, Sample::default().with_memory(&[GIB + i * 6 * MIB, i * 6 * MIB]));
+        }
+        // the samples do not cover the window yet
+        assert!(!p.matches(growth.clone()));
+        refresh(&mut p, Sample::default().with_memory(&[GIB + 24 * MIB, 24 * MIB]));
+        assert!(p.matches(growth.clone()));
+        assert_eq!(p.partial_match(growth.clone()), Some(true));
+        // the trend stays up while the growth slows down
+        refresh(&mut p, Sample::default().with_memory(&[GIB + 28 * MIB, 28 * MIB]));
+        assert!(p.matches(growth.clone()));
+
+        // growing by 40MiB per 10s
+        MockClock::set_time(Duration::ZERO);
+        let mut p = new_process();
+        for i in 0..8 {
+            refresh(&mut p, Sample::default().with_memory(&[GIB + i * 8 * MIB]));
+        }
+        assert!(!p.matches(growth.clone()));
+
+        // no process matched during the window
+        refresh(&mut p, Sample::default());
+        for i in 0..8 {
+            refresh(&mut p, Sample::default().with_memory(&[GIB + i * 20 * MIB]));
+            if i < 4 {
+                assert!(!p.matches(growth.clone()));
+            }
+        }
+        assert!(p.matches(growth.clone()));
+
+        // a single step is not a leak
+        MockClock::set_time(Duration::ZERO);
+        let mut p = new_process();
+        for i in 0..8 {
+            let step = if i < 4 { 0 } else { 60 * MIB };
+            refresh(&mut p, Sample::default().with_memory(&[GIB + step]));
+            assert!(!p.matches(growth.clone()));
+        }
+
+        // neither is a process joining the group
+        MockClock::set_time(Duration::ZERO);
+        let mut p = new_process();
+        for i in 0..8 {
+            let rss = [GIB, GIB + i * 2 * MIB, 500 * MIB];
+            refresh(&mut p, Sample::default().with_memory(&rss[..if i < 4 { 2 } else { 3 }]));
+            assert!(!p.matches(growth.clone()));
+        }
+
+        // the fastest growing process with the max aggregate
+        let growth = ProcCondition::MemoryGrowth { bytes: 50 * MIB, per: Duration::from_secs(10), aggregate: Aggregate::Max };
+        MockClock::set_time(Duration::ZERO);
+        let mut p = new_process();
+        for i in 0..6 {
+            refresh(&mut p, Sample::default().with_memory(&[GIB + i * 8 * MIB, i * 12 * MIB]));
+        }
+        assert!(p.matches(growth.clone()));
+    }
+
+    #[test]
+    fn cond_status() {
+        use ProcStatus::{DiskSleep, Running, Sleeping};
+        MockClock::set_time(Duration::ZERO);
+        let stuck = ProcCondition::Status { statuses: vec![DiskSleep], span: Duration::from_secs(4) };
+        let mut p = Process::from_pattern(PatternIn::Name("foo".to_string()))
+            .with_retention(stuck.span());
+
+        refresh(&mut p, Sample::default().with_statuses(&[Sleeping, Running]));
+        refresh(&mut p, Sample::default().with_statuses(&[Sleeping, DiskSleep]));
+        assert_eq!(p.partial_match(stuck.clone()), Some(true));
+        assert!(!p.matches(stuck.clone()));
+        refresh(&mut p, Sample::default().with_statuses(&[DiskSleep, DiskSleep]));
+        assert!(p.matches(stuck.clone()));
+        refresh(&mut p, Sample::default().with_statuses(&[Running, Sleeping]));
+        assert!(!p.matches(stuck.clone()));
+        assert_eq!(p.partial_match(stuck.clone()), Some(false));
+
+        // each process has to stay in the status on its own
+        refresh(&mut p, Sample::default().with_statuses(&[DiskSleep, Sleeping]));
+        refresh(&mut p, Sample::default().with_statuses(&[Sleeping, DiskSleep]));
+        refresh(&mut p, Sample::default().with_statuses(&[DiskSleep, Sleeping]));
+        assert!(!p.matches(stuck.clone()));
+    }
+
+    #[test]
+    fn match_exclude_status() -> anyhow::Result<(), std::io::Error> {
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-Rt5Qm")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        // SAFETY: kill has no memory safety requirements, the signal goes to the child spawned above
+        unsafe { libc::kill(target.id() as libc::pid_t, libc::SIGSTOP) };
+        std::thread::sleep(Duration::from_secs(1));
+
+        let stopped = ProcCondition::Status { statuses: vec![ProcStatus::Stopped], span: Duration::ZERO };
+        let mut p_default = Process::from_pattern(PatternIn::Name("sleep-Rt5Qm".to_string()));
+        let mut p_stopped = Process::from_pattern(PatternIn::Name("sleep-Rt5Qm".to_string()))
+            .with_exclude_status(vec![ProcStatus::Zombie]);
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+
+        // stopped processes are ignored by default
+        p_default.update_state(&sys, Instant::now());
+        assert!(p_default.pids.is_empty());
+        p_stopped.update_state(&sys, Instant::now());
+        assert_eq!(p_stopped.pids, vec![target.id() as usize]);
+        assert!(p_stopped.matches(stopped));
+
+        let _ = target.kill();
+        let _ = target.wait();
+        Ok(())
+    }
+
+    #[test]
+    fn cond_fds_threads_children() {
+        MockClock::set_time(Duration::ZERO);
+        let fds = |aggregate| ProcCondition::FdsAbove { count: 1000, span: Duration::from_secs(4), aggregate };
+        let threads = ProcCondition::ThreadsAbove { count: 200, span: Duration::ZERO, aggregate: Aggregate::Max };
+        let children = ProcCondition::ChildrenAbove { count: 10, span: Duration::ZERO, aggregate: Aggregate::Sum };
+        let mut p = Process::from_pattern(PatternIn::Name("java".to_string()))
+            .with_retention(Duration::from_secs(4));
+        let usage = |fds: &[usize], threads: &[usize], children: &[usize]| {
+            Sample::default().with_fds(fds).with_threads(threads).with_children(children)
+        };
+
+        refresh(&mut p, usage(&[600, 600], &[150, 150], &[5, 5]));
+        assert!(!p.matches(fds(Aggregate::Sum)));
+        assert_eq!(p.partial_match(fds(Aggregate::Sum)), Some(true));
+        assert!(!p.matches(threads.clone()));
+        assert!(!p.matches(children.clone()));
+        refresh(&mut p, usage(&[600, 600], &[150, 250], &[5, 6]));
+        assert!(p.matches(fds(Aggregate::Sum)));
+        assert!(!p.matches(fds(Aggregate::Max)));
+        assert!(p.matches(threads.clone()));
+        assert!(p.matches(children.clone()));
+
+        let metrics = ProcfsMetrics::default();
+        assert!(fds(Aggregate::Sum).procfs_metrics(metrics).fds);
+        assert!(threads.procfs_metrics(metrics).threads);
+        assert!(children.procfs_metrics(metrics).children);
+    }
+
+    #[test]
+    fn collect_fds_threads_children() {
+        let mut child = std::process::Command::new("tests/fake_bins/sleep-Fd2Kc")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = std::process::id() as usize;
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+
+        let metrics = ProcfsMetrics::default().with_fds().with_threads().with_children();
+        let sample = Sample::collect(&[pid], &sys, Instant::now(), None, metrics);
+        assert!(sample.fds.total > 0);
+        assert!(sample.threads.total >= 1);
+        assert!(sample.children.total >= 1);
+        let sample = Sample::collect(&[pid], &sys, Instant::now(), None, ProcfsMetrics::default());
+        assert_eq!(sample.fds.total + sample.threads.total + sample.children.total, 0);
+
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    #[test]
+    fn collect_cpu_time_and_age() {
+        let pid = std::process::id() as usize;
+        // burn some cpu time, it is counted in clock ticks
+        let start = std::time::Instant::now();
+        while procfs::cpu_time(Pid::from(pid)).unwrap().is_zero() && start.elapsed() < Duration::from_secs(2) {}
+
+        let mut sys = System::new();
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        let sample = Sample::collect(&[pid], &sys, Instant::now(), None, ProcfsMetrics::default().with_cpu_time());
+        assert!(sample.cpu_time.total > Duration::ZERO);
+        assert_eq!(sample.age, Duration::from_secs(sys.process(Pid::from(pid)).unwrap().run_time()));
+        let sample = Sample::collect(&[pid], &sys, Instant::now(), None, ProcfsMetrics::default());
+        assert_eq!(sample.cpu_time.total, Duration::ZERO);
+    }
+
+    #[test]
+    fn memory_size() {
+        const TOTAL: u64 = 16 << 30;
+        assert_eq!(parse_size("2GiB", TOTAL), Ok(2 << 30));
+        assert_eq!(parse_size("1.5 G", TOTAL), Ok(3 << 29));
+        assert_eq!(parse_size("512m", TOTAL), Ok(512 << 20));
+        assert_eq!(parse_size("2GB", TOTAL), Ok(2_000_000_000));
+        assert_eq!(parse_size("4096", TOTAL), Ok(4096));
+        assert_eq!(parse_size("25%", TOTAL), Ok(4 << 30));
+        assert!(parse_size("120%", TOTAL).is_err());
+        assert!(parse_size("2 parsecs", TOTAL).is_err());
+        assert!(parse_size("GiB", TOTAL).is_err());
+    }
+
     #[test]
     fn test_not_seen_since() {
         MockClock::set_time(Duration::ZERO);
@@ -0,0 +1,340 @@
+//! Process information that is not exposed by sysinfo, read directly from `/proc`
+
+use std::{collections::HashSet, fmt::Display, fs, io, path::{Path, PathBuf}, sync::OnceLock, time::Duration};
+
+use serde::Deserialize;
+use sysinfo::Pid;
+
+fn proc_path(pid: Pid, file: &str) -> PathBuf {
+    PathBuf::from(format!("/proc/{}/{}", pid, file))
+}
+
+/// Reads the cgroup path of a process
+pub fn cgroup(pid: Pid) -> io::Result<String> {
+    let content = fs::read_to_string(proc_path(pid, "cgroup"))?;
+    parse_cgroup(&content)
+        .map(String::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no cgroup hierarchy found"))
+}
+
+/// Extracts the cgroup path from the content of `/proc/<pid>/cgroup`.
+/// The unified (v2) hierarchy is used when available, the systemd hierarchy otherwise.
+pub fn parse_cgroup(content: &str) -> Option<&str> {
+    let hierarchy = |name: &str| {
+        content.lines().find_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+            (controllers == name).then_some(path)
+        })
+    };
+    hierarchy("").or_else(|| hierarchy("name=systemd"))
+}
+
+/// Reads the cpu time (user + system) used by a process since it started
+pub fn cpu_time(pid: Pid) -> io::Result<Duration> {
+    let content = fs::read_to_string(proc_path(pid, "stat"))?;
+    let ticks = parse_cpu_ticks(&content)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid stat file"))?;
+    Ok(Duration::from_secs_f64(ticks as f64 / clock_ticks() as f64))
+}
+
+/// Extracts the user and system time in clock ticks from the content of `/proc/<pid>/stat`.
+/// The process name may contain spaces and parentheses, fields are counted from its end.
+pub fn parse_cpu_ticks(content: &str) -> Option<u64> {
+    let (_, fields) = content.rsplit_once(')')?;
+    // utime and stime are the 14th and 15th fields, the 3rd one comes right after the name
+    let mut fields = fields.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(utime + stime)
+}
+
+/// Pids of the running processes, from the entries of `/proc`
+pub fn pids() -> io::Result<Vec<Pid>> {
+    Ok(fs::read_dir("/proc")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<usize>().ok())
+        .map(Pid::from)
+        .collect())
+}
+
+/// Number of file descriptors opened by a process, only readable for processes of the same user
+/// unless running as root or with CAP_SYS_PTRACE
+pub fn fd_count(pid: Pid) -> io::Result<usize> {
+    fd_count_in(&proc_path(pid, "fd"))
+}
+
+/// Number of file descriptors in `fd_dir`
+pub fn fd_count_in(fd_dir: &Path) -> io::Result<usize> {
+    Ok(fs::read_dir(fd_dir)?.count())
+}
+
+/// Number of threads of a process, the main thread included
+pub fn thread_count(pid: Pid) -> io::Result<usize> {
+    Ok(fs::read_dir(proc_path(pid, "task"))?.count())
+}
+
+/// Value of the `key` variable in the environment a process was started with,
+/// only readable for processes of the same user
+pub fn env_var(pid: Pid, key: &str) -> io::Result<Option<String>> {
+    let content = fs::read(proc_path(pid, "environ"))?;
+    Ok(parse_env_var(&content, key))
+}
+
+/// Extracts the value of `key` from the NUL separated content of `/proc/<pid>/environ`
+pub fn parse_env_var(content: &[u8], key: &str) -> Option<String> {
+    content.split(|b| *b == 0).find_map(|var| {
+        let value = var.strip_prefix(key.as_bytes())?.strip_prefix(b"=")?;
+        Some(String::from_utf8_lossy(value).into_owned())
+    })
+}
+
+/// Transport protocol of a socket
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Proto {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl Proto {
+    /// Socket tables of the protocol in `/proc/net`, for IPv4 and IPv6
+    fn tables(&self) -> [&'static str; 2] {
+        match self {
+            Proto::Tcp => ["tcp", "tcp6"],
+            Proto::Udp => ["udp", "udp6"],
+        }
+    }
+}
+
+impl Display for Proto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Proto::Tcp => write!(f, "tcp"),
+            Proto::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// A port of a transport protocol, `{ proto = "tcp", port = 5432 }` in the config
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct SocketPort {
+    #[serde(default)]
+    pub proto: Proto,
+    pub port: u16,
+}
+
+impl Display for SocketPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.proto, self.port)
+    }
+}
+
+// socket states, the kernel uses the TCP ones for UDP sockets as well
+const TCP_ESTABLISHED: u8 = 0x01;
+const TCP_CLOSE: u8 = 0x07;
+const TCP_LISTEN: u8 = 0x0A;
+
+/// A socket listed in the socket tables of `/proc/net`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Socket {
+    pub proto: Proto,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub state: u8,
+    pub inode: u64,
+}
+
+impl Socket {
+    /// Listening TCP socket, or UDP socket bound without a remote peer
+    pub fn is_listening(&self) -> bool {
+        match self.proto {
+            Proto::Tcp => self.state == TCP_LISTEN,
+            Proto::Udp => self.state == TCP_CLOSE && self.remote_port == 0,
+        }
+    }
+
+    /// Socket connected to a remote peer
+    pub fn is_connected(&self) -> bool {
+        self.state == TCP_ESTABLISHED
+    }
+}
+
+/// Reads the sockets of `proto` from the socket tables of the network namespace of pswatch
+pub fn sockets(proto: Proto) -> io::Result<Vec<Socket>> {
+    sockets_in(Path::new("/proc/net"), proto)
+}
+
+/// Reads the sockets of `proto` from the socket tables in `net_dir`.
+/// A missing table is skipped, the IPv6 ones do not exist when IPv6 is disabled.
+pub fn sockets_in(net_dir: &Path, proto: Proto) -> io::Result<Vec<Socket>> {
+    let mut sockets = vec![];
+    for table in proto.tables() {
+        match fs::read_to_string(net_dir.join(table)) {
+            Ok(content) => sockets.extend(parse_sockets(&content, proto)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(sockets)
+}
+
+/// Parses the content of a socket table like `/proc/net/tcp`, the header line is skipped
+pub fn parse_sockets(content: &str, proto: Proto) -> Vec<Socket> {
+    content.lines().filter_map(|line| parse_socket(line, proto)).collect()
+}
+
+fn parse_socket(line: &str, proto: Proto) -> Option<Socket> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    // addresses are `<hex ip>:<hex port>`
+    let port = |addr: &str| u16::from_str_radix(addr.rsplit_once(':')?.1, 16).ok();
+    Some(Socket {
+        proto,
+        local_port: port(fields.get(1)?)?,
+        remote_port: port(fields.get(2)?)?,
+        state: u8::from_str_radix(fields.get(3)?, 16).ok()?,
+        inode: fields.get(9)?.parse().ok()?,
+    })
+}
+
+/// Inodes of the sockets opened by a process, only readable for processes of the same user
+pub fn socket_inodes(pid: Pid) -> io::Result<HashSet<u64>> {
+    socket_inodes_in(&proc_path(pid, "fd"))
+}
+
+/// Inodes of the sockets linked from the file descriptors in `fd_dir`
+pub fn socket_inodes_in(fd_dir: &Path) -> io::Result<HashSet<u64>> {
+    Ok(fs::read_dir(fd_dir)?
+        .filter_map(|entry| fs::read_link(entry.ok()?.path()).ok())
+        .filter_map(|link| parse_socket_link(&link))
+        .collect())
+}
+
+/// Extracts the inode of a file descriptor link to a socket: `socket:[<inode>]`
+fn parse_socket_link(link: &Path) -> Option<u64> {
+    link.to_str()?
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+/// Number of clock ticks per second used by the kernel for process times
+fn clock_ticks() -> u64 {
+    static CLOCK_TICKS: OnceLock<u64> = OnceLock::new();
+    *CLOCK_TICKS.get_or_init(|| {
+        // SAFETY: sysconf has no memory safety requirements
+        match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+            ticks if ticks > 0 => ticks as u64,
+            _ => 100,
+        }
+    })
+}
+
+/// Innermost systemd unit (service or scope) of a cgroup path
+pub fn systemd_unit(cgroup: &str) -> Option<&str> {
+    cgroup
+        .rsplit('/')
+        .find(|unit| unit.ends_with(".service") || unit.ends_with(".scope"))
+}
+
+/// Innermost systemd slice of a cgroup path
+pub fn systemd_slice(cgroup: &str) -> Option<&str> {
+    cgroup.rsplit('/').find(|unit| unit.ends_with(".slice"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn cgroup_v2() {
+        let content = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox-4242.scope\n";
+        let cgroup = parse_cgroup(content).unwrap();
+        assert_eq!(
+            cgroup,
+            "/user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox-4242.scope"
+        );
+        assert_eq!(systemd_unit(cgroup), Some("app-firefox-4242.scope"));
+        assert_eq!(systemd_slice(cgroup), Some("app.slice"));
+    }
+
+    #[test]
+    fn cgroup_v1() {
+        let content = indoc! {"
+            12:pids:/system.slice/sshd.service
+            4:memory:/system.slice/sshd.service
+            1:name=systemd:/system.slice/sshd.service
+        "};
+        let cgroup = parse_cgroup(content).unwrap();
+        assert_eq!(cgroup, "/system.slice/sshd.service");
+        assert_eq!(systemd_unit(cgroup), Some("sshd.service"));
+        assert_eq!(systemd_slice(cgroup), Some("system.slice"));
+    }
+
+    #[test]
+    fn stat_cpu_ticks() {
+        let content = "4242 (tmux: server) S 1 4242 4242 0 -1 4194368 1523 0 0 0 250 130 0 0 20 0 1 0 1234 0 0\n";
+        assert_eq!(parse_cpu_ticks(content), Some(380));
+        let content = "42 (a) b) R 1 42 42 0 -1 0 0 0 0 0 7 3 0 0 20 0 1 0 1 0 0\n";
+        assert_eq!(parse_cpu_ticks(content), Some(10));
+        assert_eq!(parse_cpu_ticks("42 (truncated) S 1 2"), None);
+    }
+
+    #[test]
+    fn environ_var() {
+        let content = b"HOME=/home/me\0PSWATCH_SPAWNED_BY=4242\0PSWATCH=\0";
+        assert_eq!(parse_env_var(content, "PSWATCH_SPAWNED_BY"), Some("4242".into()));
+        assert_eq!(parse_env_var(content, "PSWATCH"), Some("".into()));
+        assert_eq!(parse_env_var(content, "HOM"), None);
+    }
+
+    #[test]
+    fn fds_and_threads() -> io::Result<()> {
+        // the fds of the test process change with the tests running in parallel
+        assert_eq!(fd_count_in(Path::new("tests/fixtures/proc/fd"))?, 4);
+        let pid = Pid::from_u32(std::process::id());
+        assert!(fd_count(pid)? >= 1);
+        assert!(thread_count(pid)? >= 1);
+        assert!(pids()?.contains(&pid));
+        assert!(fd_count(Pid::from(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn socket_tables() -> io::Result<()> {
+        let net = Path::new("tests/fixtures/proc/net");
+        let tcp = sockets_in(net, Proto::Tcp)?;
+        assert_eq!(tcp.len(), 5);
+        let listening: Vec<u16> = tcp.iter().filter(|s| s.is_listening()).map(|s| s.local_port).collect();
+        assert_eq!(listening, vec![5432, 631, 22]);
+        let connected: Vec<&Socket> = tcp.iter().filter(|s| s.is_connected()).collect();
+        assert_eq!(connected.len(), 1);
+        assert_eq!((connected[0].remote_port, connected[0].inode), (5432, 34567));
+
+        // there is no udp6 table
+        let udp = sockets_in(net, Proto::Udp)?;
+        assert_eq!(udp.len(), 2);
+        assert!(udp[0].is_listening() && udp[0].local_port == 51820);
+        assert!(udp[1].is_connected() && !udp[1].is_listening() && udp[1].remote_port == 51820);
+        Ok(())
+    }
+
+    #[test]
+    fn fd_socket_links() -> io::Result<()> {
+        let inodes = socket_inodes_in(Path::new("tests/fixtures/proc/fd"))?;
+        assert_eq!(inodes, HashSet::from([23456, 34567]));
+        assert_eq!(parse_socket_link(Path::new("socket:[42]")), Some(42));
+        assert_eq!(parse_socket_link(Path::new("anon_inode:[eventfd]")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn cgroup_root() {
+        assert_eq!(parse_cgroup("0::/\n"), Some("/"));
+        assert_eq!(systemd_unit("/"), None);
+        assert_eq!(parse_cgroup("4:memory:/foo\n"), None);
+    }
+}
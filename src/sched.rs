@@ -1,6 +1,6 @@
-use std::{process::Command, sync::OnceLock, thread::sleep, time::Duration};
+use std::{collections::{HashMap, HashSet}, process::Command, sync::OnceLock, thread::sleep, time::Duration};
 
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 
 #[cfg(test)]
 use mock_instant::thread_local::Instant;
@@ -8,11 +8,11 @@ use mock_instant::thread_local::Instant;
 #[cfg(not(test))]
 use std::time::Instant;
 
-use sysinfo::{ProcessRefreshKind, RefreshKind, System, UpdateKind};
+use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System, UpdateKind};
 
-use crate::config::{CmdSchedule, Profile};
+use crate::config::{CmdSchedule, Profile, TrackMode};
 use crate::matching::ProcessMatcher;
-use crate::process::ProcLifetime;
+use crate::process::{ProcCondition, ProcLifetime, ProcState, ProcfsMetrics, INSTANCE_PID_ENV, SPAWNED_BY_ENV};
 use crate::state::{ConditionMatcher, StateTracker};
 
 use super::process::Process;
@@ -20,6 +20,9 @@ use super::process::Process;
 /// A job that can run in the scheduler
 trait Job {
     fn update(&mut self, sysinfo: &System, last_refresh: Instant);
+
+    /// Extends `kind` with the process data needed by the job
+    fn refresh_kind(&self, kind: ProcessRefreshKind) -> ProcessRefreshKind;
 }
 
 pub(crate) struct ProfileJob<T>
@@ -34,26 +37,126 @@ where
 
 impl ProfileJob<Process> {
     pub fn from_profile(profile: Profile) -> Self {
+        warn_excluded_status(&profile);
+        Self {
+            object: profile_process(&profile),
+            profile,
+        }
+    }
+}
+
+/// A job tracking every PID matched by the profile on its own
+pub(crate) struct InstancesJob {
+    profile: Profile,
+
+    /// matches the instances, its own state is not tracked
+    matcher: Process,
+
+    /// instances are dropped once not seen for longer than the conditions span
+    retention: Duration,
+
+    instances: HashMap<InstanceKey, Instance>,
 
+    /// whether the processes were matched once already, the instances found later were started
+    refreshed: bool,
+}
+
+/// Identifies a process instance, the start time tells apart reused PIDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct InstanceKey {
+    pid: usize,
+    start_time: u64,
+}
+
+struct Instance {
+    object: Process,
+
+    /// per instance copy of the profile commands
+    commands: Vec<CmdSchedule>,
+}
+
+impl InstancesJob {
+    pub fn from_profile(profile: Profile) -> Self {
+        warn_excluded_status(&profile);
         Self {
-            profile: profile.clone(),
-            object: Process::build(profile.matching, ProcLifetime::new()),
+            matcher: profile_process(&profile),
+            retention: retention(&profile),
+            instances: HashMap::new(),
+            refreshed: false,
+            profile,
         }
     }
 }
 
-fn run_cmd(cmd: &mut CmdSchedule, matching: ProcessMatcher, exec_end: bool) {
+/// Longest span the conditions of the profile look back at
+fn retention(profile: &Profile) -> Duration {
+    profile
+        .commands
+        .iter()
+        .map(|cmd| cmd.condition.span())
+        .max()
+        .unwrap_or_default()
+}
+
+/// Status conditions never match the processes ignored by `exclude_status`
+fn warn_excluded_status(profile: &Profile) {
+    for cmd in &profile.commands {
+        if let ProcCondition::Status { statuses, .. } = &cmd.condition {
+            statuses
+                .iter()
+                .filter(|status| profile.exclude_status.contains(status))
+                .for_each(|status| {
+                    warn!("<{}>: {:?} processes are excluded by the profile, set `exclude_status` to match them",
+                        profile.matching, status)
+                });
+        }
+    }
+}
+
+/// Builds the process tracked by the profile
+fn profile_process(profile: &Profile) -> Process {
+    let procfs_metrics = profile
+        .commands
+        .iter()
+        .fold(ProcfsMetrics::default(), |m, cmd| cmd.condition.procfs_metrics(m));
+
+    Process::build(profile.matching.clone(), ProcLifetime::new())
+        .with_exclude(profile.exclude.clone())
+        .with_exclude_self(profile.exclude_self)
+        .with_retention(retention(profile))
+        .with_procfs_metrics(procfs_metrics)
+        .with_descendants(profile.include_descendants)
+        .with_exclude_status(profile.exclude_status.clone())
+}
+
+/// Extends `kind` with the process data needed by the profile
+fn profile_refresh_kind(profile: &Profile, object: &Process, kind: ProcessRefreshKind) -> ProcessRefreshKind {
+    profile
+        .commands
+        .iter()
+        .fold(object.refresh_kind(kind), |kind, cmd| cmd.condition.refresh_kind(kind))
+}
+
+fn run_cmd(cmd: &mut CmdSchedule, matching: ProcessMatcher, exec_end: bool, pid: Option<usize>) {
 
     // handle end exec
-    let out = if exec_end && cmd.exec_end.is_some() {
-        Command::new(&cmd.exec_end.as_ref().unwrap()[0]).args(&cmd.exec_end.as_ref().unwrap()[1..]).output()
-    } else if exec_end && cmd.exec_end.is_none() {
-        return;
+    let exec = match (exec_end, &cmd.exec_end) {
+        (true, Some(exec_end)) => exec_end,
+        (true, None) => return,
         // run normal execs
-    } else {
-        Command::new(&cmd.exec[0]).args(&cmd.exec[1..]).output()
+        (false, _) => &cmd.exec,
     };
 
+    // mark the spawned processes so they are never matched by pswatch itself
+    let mut command = Command::new(&exec[0]);
+    command
+        .args(&exec[1..])
+        .env(SPAWNED_BY_ENV, std::process::id().to_string());
+    if let Some(pid) = pid {
+        command.env(INSTANCE_PID_ENV, pid.to_string());
+    }
+    let out = command.output();
+
 
     match out {
         Ok(output) => {
@@ -78,50 +181,124 @@ fn run_cmd(cmd: &mut CmdSchedule, matching: ProcessMatcher, exec_end: bool) {
     }
 }
 
+/// Runs the commands whose condition is met by `object`, `pid` is passed along to the commands
+/// of a process instance
+fn run_commands(commands: &mut [CmdSchedule], object: &Process, matching: &ProcessMatcher, pid: Option<usize>) {
+    // run commands when entering match state `exec`
+    commands.iter_mut()
+        // only process enabled commands
+        .filter(|cmd| !cmd.disabled)
+        .filter(|cmd| object.matches(cmd.condition.clone()))
+        .for_each(|cmd| {
+            debug!("running exec cmd");
+
+            match object.edge_pids(&cmd.condition) {
+                // edge conditions run the command once per started or exited process
+                Some(pids) => {
+                    for pid in pids {
+                        if cmd.disabled {
+                            break;
+                        }
+                        run_cmd(cmd, matching.clone(), false, Some(*pid));
+                    }
+                }
+                None => run_cmd(cmd, matching.clone(), false, pid),
+            }
+        });
+
+    // run commands on exit of matching state `exec_end`
+    if object.exiting() {
+        commands.iter_mut()
+            .filter(|cmd| !cmd.condition.is_edge())
+            .for_each(|cmd| {
+                if !object.partial_match(cmd.condition.clone()).is_some_and(|m| m) {
+                    run_cmd(cmd, matching.clone(), true, pid);
+                }
+            });
+    }
+
+    // if object does not match since 2 cycles, reset the run_once state
+    commands.iter_mut()
+        .filter(|cmd| cmd.disabled && cmd.run_once)
+        .for_each(|cmd| {
+            if !object.matches(cmd.condition.clone()) &&
+            object.prev_state().is_some_and(|s| s == object.state()) {
+                debug!("disabling cmd");
+                cmd.disabled = false;
+            }
+        });
+}
+
 impl Job for ProfileJob<Process> {
 
+    fn refresh_kind(&self, kind: ProcessRefreshKind) -> ProcessRefreshKind {
+        profile_refresh_kind(&self.profile, &self.object, kind)
+    }
+
 
     fn update(&mut self, sysinfo: &System, last_refresh: Instant) {
         let _ = self.object.update_state(sysinfo, last_refresh);
 
         trace!("{:#?}", &self.object);
-        // run commands when entering match state `exec`
-        self.profile.commands.iter_mut()
-            // only process enabled commands
-            .filter(|cmd| !cmd.disabled)
-            .filter(|cmd| self.object.matches(cmd.condition.clone()))
-            .for_each(|cmd| {
-                debug!("running exec cmd");
+        run_commands(&mut self.profile.commands, &self.object, &self.profile.matching, None);
+    }
+}
 
-                run_cmd(cmd, self.profile.matching.clone(), false);
-            });
+impl Job for InstancesJob {
 
-        // run commands on exit of matching state `exec_end`
-        if self.object.exiting() {
-            self.profile.commands.iter_mut()
-                .for_each(|cmd| {
-                    if !self.object.partial_match(cmd.condition.clone()).is_some_and(|m| m) {
-                        run_cmd(cmd, self.profile.matching.clone(), true);
-                    }
-                });
+    fn refresh_kind(&self, kind: ProcessRefreshKind) -> ProcessRefreshKind {
+        profile_refresh_kind(&self.profile, &self.matcher, kind)
+    }
+
+    fn update(&mut self, sysinfo: &System, last_refresh: Instant) {
+        let matched: HashSet<InstanceKey> = self
+            .matcher
+            .match_pids(sysinfo)
+            .into_iter()
+            .filter_map(|pid| sysinfo.process(Pid::from(pid)))
+            .map(|proc| InstanceKey { pid: proc.pid().into(), start_time: proc.start_time() })
+            .collect();
+
+        for key in &matched {
+            if !self.instances.contains_key(key) {
+                debug!("<{}> new instance: {}", self.profile.matching, key.pid);
+                let instance = Instance {
+                    object: profile_process(&self.profile).with_report_running(self.refreshed),
+                    commands: self.profile.commands.clone(),
+                };
+                self.instances.insert(*key, instance);
+            }
         }
 
-        // if object does not match since 2 cycles, reset the run_once state
-        self.profile.commands.iter_mut()
-            .filter(|cmd| cmd.disabled && cmd.run_once)
-            .for_each(|cmd| {
-                if !self.object.matches(cmd.condition.clone()) &&
-                self.object.prev_state().is_some_and(|s| s == self.object.state()) {
-                    debug!("disabling cmd");
-                    cmd.disabled = false;
-                }
-            });
+        for (key, instance) in self.instances.iter_mut() {
+            let pids = if matched.contains(key) { vec![key.pid] } else { vec![] };
+            let _ = instance.object.track(pids, sysinfo, last_refresh);
+
+            trace!("{:#?}", &instance.object);
+            run_commands(&mut instance.commands, &instance.object, &self.profile.matching, Some(key.pid));
+        }
+        self.refreshed = true;
+
+        // an instance is evaluated at least once more after it stopped matching, its
+        // `not_seen` commands run before it is dropped
+        let retention = self.retention;
+        self.instances.retain(|key, instance| {
+            let gone = instance.object.prev_state().is_some_and(|s| s == ProcState::NotSeen)
+                && instance.object.unseen_for().is_some_and(|t| t > retention);
+            if gone {
+                debug!("<{}> instance gone: {}", self.profile.matching, key.pid);
+            }
+            !gone
+        });
     }
 }
 
 pub struct Scheduler {
     system_info: System,
     jobs: Vec<Box<dyn Job>>,
+
+    /// process data refreshed on every cycle
+    refresh_specs: RefreshKind,
 }
 
 static PROCESS_REFRESH_SPECS: OnceLock<RefreshKind> = OnceLock::new();
@@ -140,12 +317,23 @@ impl Scheduler {
         })
     }
 
+    /// Extends the base refresh specs with the process data needed by `processes`
+    pub fn refresh_specs_for<'a>(processes: impl IntoIterator<Item = &'a Process>) -> RefreshKind {
+        let specs = Self::process_refresh_specs();
+        let process_refresh_kind = processes
+            .into_iter()
+            .fold(specs.processes().unwrap_or_default(), |kind, p| p.refresh_kind(kind));
+
+        specs.with_processes(process_refresh_kind)
+    }
+
     pub fn new() -> Self {
         debug!("Using sampling rate of {:?}.", Self::SAMPLING_RATE);
 
         Self {
             system_info: System::new(),
             jobs: Vec::new(),
+            refresh_specs: Self::process_refresh_specs(),
         }
     }
 
@@ -155,18 +343,28 @@ impl Scheduler {
         let mut jobs: Vec<Box<dyn Job>> = Vec::with_capacity(profiles.len());
         profiles
             .into_iter()
-            .map(ProfileJob::from_profile)
-            .for_each(|pj| jobs.push(Box::new(pj)));
+            .for_each(|profile| match profile.track {
+                TrackMode::Group => jobs.push(Box::new(ProfileJob::from_profile(profile))),
+                TrackMode::PerPid => jobs.push(Box::new(InstancesJob::from_profile(profile))),
+            });
+
+        let specs = Self::process_refresh_specs();
+        let process_refresh_kind = jobs
+            .iter()
+            .fold(specs.processes().unwrap_or_default(), |kind, job| job.refresh_kind(kind));
+        let refresh_specs = specs.with_processes(process_refresh_kind);
+        debug!("process refresh specs: {:?}", refresh_specs);
 
         Self {
             system_info: System::new(),
             jobs,
+            refresh_specs,
         }
     }
 
     fn refresh_proc_info(&mut self) {
         self.system_info
-            .refresh_specifics(Self::process_refresh_specs());
+            .refresh_specifics(self.refresh_specs);
     }
 
     pub fn run(&mut self) {
@@ -191,3 +389,155 @@ impl Default for Scheduler {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mock_instant::thread_local::MockClock;
+
+    #[test]
+    fn run_edge_commands() {
+        let out = std::env::temp_dir().join(format!("pswatch-edge-{}", std::process::id()));
+        let profile: Profile = toml::from_str(&format!(
+            r#"
+            matching = {{ name = "foo" }}
+
+            [[commands]]
+            condition = "started"
+            exec = ["sh", "-c", "echo started $PSWATCH_PID >> {0}"]
+            exec_end = ["sh", "-c", "echo end >> {0}"]
+            "#,
+            out.display()
+        ))
+        .unwrap();
+        let mut commands = profile.commands.clone();
+        let mut object = profile_process(&profile);
+        let sys = System::new();
+
+        // the command runs once for each started process
+        object.track(vec![], &sys, Instant::now());
+        run_commands(&mut commands, &object, &profile.matching, None);
+        object.track(vec![1, 2], &sys, Instant::now());
+        run_commands(&mut commands, &object, &profile.matching, None);
+        object.track(vec![1, 2], &sys, Instant::now());
+        run_commands(&mut commands, &object, &profile.matching, None);
+        // edge conditions have no end state
+        object.track(vec![], &sys, Instant::now());
+        run_commands(&mut commands, &object, &profile.matching, None);
+
+        let mut lines: Vec<String> = std::fs::read_to_string(&out).unwrap().lines().map(String::from).collect();
+        lines.sort();
+        assert_eq!(lines, vec!["started 1", "started 2"]);
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn track_per_pid() {
+        let spawn = || {
+            std::process::Command::new("tests/fake_bins/sleep-Jq7Wd")
+                .arg("300")
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .unwrap()
+        };
+        let mut targets = [spawn(), spawn()];
+        let out = std::env::temp_dir().join(format!("pswatch-per-pid-{}", std::process::id()));
+        let profile: Profile = toml::from_str(&format!(
+            r#"
+            matching = {{ name = "sleep-Jq7Wd" }}
+            track = "per_pid"
+
+            [[commands]]
+            condition = {{ seen = "1s" }}
+            exec = ["sh", "-c", "echo seen $PSWATCH_PID >> {0}"]
+            exec_end = ["sh", "-c", "echo end $PSWATCH_PID >> {0}"]
+            run_once = true
+            "#,
+            out.display()
+        ))
+        .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut job = InstancesJob::from_profile(profile);
+        let mut sys = System::new();
+        MockClock::set_time(Duration::ZERO);
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        job.update(&sys, Instant::now());
+        assert_eq!(job.instances.len(), 2);
+        assert!(!out.exists(), "instances not seen for long enough");
+
+        // every instance runs its own command
+        MockClock::advance(Duration::from_secs(2));
+        job.update(&sys, Instant::now());
+        let mut lines: Vec<String> = std::fs::read_to_string(&out).unwrap().lines().map(String::from).collect();
+        lines.sort();
+        let mut expected: Vec<String> = targets.iter().map(|t| format!("seen {}", t.id())).collect();
+        expected.sort();
+        assert_eq!(lines, expected);
+
+        // the instance of the killed process ends on its own
+        let _ = targets[0].kill().and_then(|_| targets[0].wait());
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        job.update(&sys, Instant::now());
+        let content = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(content.lines().last(), Some(format!("end {}", targets[0].id()).as_str()));
+        assert_eq!(content.lines().count(), 3);
+
+        // dropped once not seen for longer than the conditions span
+        MockClock::advance(Duration::from_secs(2));
+        job.update(&sys, Instant::now());
+        assert_eq!(job.instances.len(), 1);
+        assert!(job.instances.keys().all(|key| key.pid == targets[1].id() as usize));
+
+        let _ = targets[1].kill().and_then(|_| targets[1].wait());
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn instance_not_seen_commands() {
+        let mut target = std::process::Command::new("tests/fake_bins/sleep-Ex4Vb")
+            .arg("300")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let out = std::env::temp_dir().join(format!("pswatch-per-pid-end-{}", std::process::id()));
+        let profile: Profile = toml::from_str(&format!(
+            r#"
+            matching = {{ name = "sleep-Ex4Vb" }}
+            track = "per_pid"
+
+            [[commands]]
+            condition = "exited"
+            exec = ["sh", "-c", "echo exited >> {0}"]
+
+            [[commands]]
+            condition = {{ not_seen = "2s" }}
+            exec = ["sh", "-c", "echo not seen >> {0}"]
+            "#,
+            out.display()
+        ))
+        .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut job = InstancesJob::from_profile(profile);
+        let mut sys = System::new();
+        MockClock::set_time(Duration::ZERO);
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        job.update(&sys, Instant::now());
+        assert_eq!(job.instances.len(), 1);
+
+        let _ = target.kill().and_then(|_| target.wait());
+        sys.refresh_specifics(Scheduler::process_refresh_specs());
+        MockClock::advance(Duration::from_secs(2));
+        job.update(&sys, Instant::now());
+        assert_eq!(job.instances.len(), 1, "instance kept after it stopped matching");
+
+        MockClock::advance(Duration::from_secs(2));
+        job.update(&sys, Instant::now());
+        assert!(job.instances.is_empty());
+        let content = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(content.lines().collect::<Vec<_>>(), vec!["exited", "not seen"]);
+
+        let _ = std::fs::remove_file(&out);
+    }
+}
@@ -0,0 +1 @@
+proc-50aPYe1K.sh
\ No newline at end of file
@@ -51,6 +51,7 @@ fn match_cond_seen(
     assert_eq!(p.matches(cond), should_match,
     "process should be seen");
     let _ = target.kill();
+    let _ = target.wait();
 }
 
 // cond: not seen
@@ -132,6 +133,7 @@ fn match_cond_not_seen_2(
     assert!(matches!(p.state(), ProcState::Seen));
 
     let _ = target.kill();
+    let _ = target.wait();
 
     std::thread::sleep(Duration::from_millis(10));
     s.refresh_specifics(Scheduler::process_refresh_specs());